@@ -0,0 +1,339 @@
+use sea_orm_migration::prelude::*;
+
+/// Profiles, moderation and the account history added on top of the original schema.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column_if_not_exists(ColumnDef::new(User::Username).string().null())
+                    .add_column_if_not_exists(ColumnDef::new(User::Bio).string().null())
+                    .add_column_if_not_exists(ColumnDef::new(User::Website).string().null())
+                    .add_column_if_not_exists(ColumnDef::new(User::Locale).string().null())
+                    .add_column_if_not_exists(ColumnDef::new(User::Timezone).string().null())
+                    .add_column_if_not_exists(ColumnDef::new(User::Pronouns).string().null())
+                    .add_column_if_not_exists(ColumnDef::new(User::Birthday).date().null())
+                    .add_column_if_not_exists(
+                        ColumnDef::new(User::ProfileVisibility)
+                            .json_binary()
+                            .not_null()
+                            .default(Expr::cust("'{}'::jsonb")),
+                    )
+                    .add_column_if_not_exists(
+                        ColumnDef::new(User::Role)
+                            .string_len(16)
+                            .not_null()
+                            .default("user"),
+                    )
+                    .add_column_if_not_exists(
+                        ColumnDef::new(User::DeletionScheduledAt).date_time().null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Accounts from before handles existed get one made from their id, which they can
+        // change later.
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"UPDATE "user" SET "username" = 'user_' || "id" WHERE "username" IS NULL"#,
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .modify_column(ColumnDef::new(User::Username).string().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_user_username")
+                    .table(User::Table)
+                    .col(User::Username)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(AuditEvent::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(AuditEvent::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(AuditEvent::ActorId).integer().null())
+                    .col(ColumnDef::new(AuditEvent::SubjectId).integer().null())
+                    .col(
+                        ColumnDef::new(AuditEvent::EventType)
+                            .string_len(32)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(AuditEvent::Ip).string().null())
+                    .col(ColumnDef::new(AuditEvent::UserAgent).string().null())
+                    .col(ColumnDef::new(AuditEvent::ClientId).integer().null())
+                    .col(
+                        ColumnDef::new(AuditEvent::Details)
+                            .json_binary()
+                            .not_null()
+                            .default(Expr::cust("'{}'::jsonb")),
+                    )
+                    .col(
+                        ColumnDef::new(AuditEvent::CreatedAt)
+                            .date_time()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_audit_event_actor_id")
+                    .table(AuditEvent::Table)
+                    .col(AuditEvent::ActorId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_audit_event_subject_id")
+                    .table(AuditEvent::Table)
+                    .col(AuditEvent::SubjectId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(KnownDevice::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(KnownDevice::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(KnownDevice::UserId).integer().not_null())
+                    .col(ColumnDef::new(KnownDevice::Fingerprint).string().not_null())
+                    .col(ColumnDef::new(KnownDevice::IpRange).string().not_null())
+                    .col(
+                        ColumnDef::new(KnownDevice::FirstSeenAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(KnownDevice::LastSeenAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_known_device_user_id")
+                    .table(KnownDevice::Table)
+                    .col(KnownDevice::UserId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(UsernameRedirect::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UsernameRedirect::Username)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(UsernameRedirect::UserId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(UsernameRedirect::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(UsernameRedirect::ExpiresAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_username_redirect_user_id")
+                    .table(UsernameRedirect::Table)
+                    .col(UsernameRedirect::UserId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Suspension::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Suspension::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Suspension::UserId).integer().not_null())
+                    .col(ColumnDef::new(Suspension::Reason).string().not_null())
+                    .col(ColumnDef::new(Suspension::Note).string().null())
+                    .col(ColumnDef::new(Suspension::ModeratorId).integer().null())
+                    .col(ColumnDef::new(Suspension::StartsAt).date_time().not_null())
+                    .col(ColumnDef::new(Suspension::EndsAt).date_time().null())
+                    .col(ColumnDef::new(Suspension::LiftedAt).date_time().null())
+                    .col(ColumnDef::new(Suspension::LiftedBy).integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_suspension_user_id")
+                    .table(Suspension::Table)
+                    .col(Suspension::UserId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for table in [
+            Suspension::Table.into_iden(),
+            UsernameRedirect::Table.into_iden(),
+            KnownDevice::Table.into_iden(),
+            AuditEvent::Table.into_iden(),
+        ] {
+            manager
+                .drop_table(Table::drop().table(table).if_exists().to_owned())
+                .await?;
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .drop_column(User::Username)
+                    .drop_column(User::Bio)
+                    .drop_column(User::Website)
+                    .drop_column(User::Locale)
+                    .drop_column(User::Timezone)
+                    .drop_column(User::Pronouns)
+                    .drop_column(User::Birthday)
+                    .drop_column(User::ProfileVisibility)
+                    .drop_column(User::Role)
+                    .drop_column(User::DeletionScheduledAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Username,
+    Bio,
+    Website,
+    Locale,
+    Timezone,
+    Pronouns,
+    Birthday,
+    ProfileVisibility,
+    Role,
+    DeletionScheduledAt,
+}
+
+#[derive(DeriveIden)]
+enum AuditEvent {
+    Table,
+    Id,
+    ActorId,
+    SubjectId,
+    EventType,
+    Ip,
+    UserAgent,
+    ClientId,
+    Details,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum KnownDevice {
+    Table,
+    Id,
+    UserId,
+    Fingerprint,
+    IpRange,
+    FirstSeenAt,
+    LastSeenAt,
+}
+
+#[derive(DeriveIden)]
+enum UsernameRedirect {
+    Table,
+    Username,
+    UserId,
+    CreatedAt,
+    ExpiresAt,
+}
+
+#[derive(DeriveIden)]
+enum Suspension {
+    Table,
+    Id,
+    UserId,
+    Reason,
+    Note,
+    ModeratorId,
+    StartsAt,
+    EndsAt,
+    LiftedAt,
+    LiftedBy,
+}
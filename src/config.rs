@@ -0,0 +1,870 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use lettre::message::Mailbox;
+use lettre::SmtpTransport;
+use redis::IntoConnectionInfo;
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::middleware::rate_limit::{self, Algorithm};
+
+/// Everything the server can be configured with.
+///
+/// Read from the TOML file named by `CONFIG_FILE` (`config.toml` by default, which may be
+/// missing), after which the environment variables listed on each field take precedence.
+/// Durations carry their unit in their name.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub store: StoreConfig,
+    pub auth: AuthConfig,
+    pub tokens: TokensConfig,
+    pub lockout: LockoutConfig,
+    pub accounts: AccountsConfig,
+    pub smtp: SmtpConfig,
+    pub captcha: CaptchaConfig,
+    pub avatars: AvatarsConfig,
+    pub geoip: GeoIpConfig,
+    pub health: HealthConfig,
+    pub telemetry: TelemetryConfig,
+    /// Overrides the algorithm of the rate limit with the given name, one of
+    /// [`rate_limit::NAMES`], as `token_bucket:<capacity>:<refill per second>` or
+    /// `sliding_window:<limit>:<window in seconds>`. `RATE_LIMIT_<NAME>`.
+    pub rate_limits: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// `LISTEN_ADDR`.
+    pub listen: SocketAddr,
+    /// Where the frontend is served, which links in emails point to. `PUBLIC_URL`.
+    pub public_url: String,
+    /// Applies pending migrations before starting. `AUTO_MIGRATE`.
+    pub auto_migrate: bool,
+    /// How long to keep serving with readiness failing after a shutdown signal, so that
+    /// load balancers stop routing here first. `SHUTDOWN_DRAIN_SECONDS`.
+    pub drain_seconds: u64,
+    /// How long to then wait for in-flight requests. `SHUTDOWN_TIMEOUT_SECONDS`.
+    pub shutdown_timeout_seconds: u64,
+    /// Serves `/metrics` on this address only. Without it, `/metrics` is served next to
+    /// the API to admins only. `METRICS_LISTEN_ADDR`.
+    pub metrics_listen: Option<SocketAddr>,
+    /// How many reverse proxies in front of the server append to `X-Forwarded-For`.
+    /// With none, clients are told apart by the address they connect from.
+    /// `TRUSTED_PROXIES`.
+    pub trusted_proxies: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            public_url: "https://nuke.websxz.org".to_string(),
+            auto_migrate: false,
+            drain_seconds: 5,
+            shutdown_timeout_seconds: 30,
+            metrics_listen: None,
+            trusted_proxies: 0,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The frontend URL of `path`, which starts with a slash.
+    pub fn link(&self, path: &str) -> String {
+        format!("{}{}", self.public_url.trim_end_matches('/'), path)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// Required. `DB_URL`.
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+    #[default]
+    Redis,
+    /// Only works for a single instance, and loses everything on restart.
+    Memory,
+}
+
+impl FromStr for StoreBackend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redis" => Ok(StoreBackend::Redis),
+            "memory" => Ok(StoreBackend::Memory),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreConfig {
+    /// `EPHEMERAL_STORE`.
+    pub backend: StoreBackend,
+    /// Required with the Redis backend. `REDIS_URL`.
+    pub redis_url: Option<String>,
+    /// `REDIS_CONNECT_TIMEOUT_MS`.
+    pub connect_timeout_ms: u64,
+    /// `REDIS_RESPONSE_TIMEOUT_MS`.
+    pub response_timeout_ms: u64,
+    /// `REDIS_RECONNECT_RETRIES`.
+    pub reconnect_retries: usize,
+    /// `REDIS_RECONNECT_MAX_DELAY_MS`.
+    pub reconnect_max_delay_ms: u64,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        StoreConfig {
+            backend: StoreBackend::Redis,
+            redis_url: None,
+            connect_timeout_ms: 2000,
+            response_timeout_ms: 1000,
+            reconnect_retries: 6,
+            reconnect_max_delay_ms: 5000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Required. `JWT_SECRET`.
+    pub jwt_secret: String,
+    /// Users that are always admins, so that the first one can be set up. `ADMIN_UIDS`,
+    /// separated by commas.
+    pub admin_uids: Vec<i32>,
+    /// `ACCESS_TOKEN_TTL_SECONDS`.
+    pub access_token_ttl_seconds: u64,
+    /// `REFRESH_TOKEN_TTL_SECONDS`.
+    pub refresh_token_ttl_seconds: u64,
+    /// `AUTHORIZATION_CODE_TTL_SECONDS`.
+    pub authorization_code_ttl_seconds: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            jwt_secret: String::new(),
+            admin_uids: Vec::new(),
+            access_token_ttl_seconds: 30 * 60,
+            refresh_token_ttl_seconds: 3 * 30 * 24 * 60 * 60,
+            authorization_code_ttl_seconds: 5 * 60,
+        }
+    }
+}
+
+/// Lifetimes and cooldowns of the tokens and codes sent by email.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokensConfig {
+    /// `VERIFICATION_TTL_SECONDS`.
+    pub verification_ttl_seconds: u64,
+    /// `VERIFICATION_RESEND_COOLDOWN_SECONDS`.
+    pub verification_resend_cooldown_seconds: u64,
+    /// `EMAIL_LOGIN_LINK_TTL_SECONDS`.
+    pub email_login_link_ttl_seconds: u64,
+    /// `EMAIL_LOGIN_CODE_TTL_SECONDS`.
+    pub email_login_code_ttl_seconds: u64,
+    /// Wrong guesses after which a login code is discarded. `EMAIL_LOGIN_MAX_CODE_ATTEMPTS`.
+    pub email_login_max_code_attempts: u32,
+    /// `UNLOCK_LINK_TTL_SECONDS`.
+    pub unlock_link_ttl_seconds: u64,
+    /// `UNLOCK_EMAIL_COOLDOWN_SECONDS`.
+    pub unlock_email_cooldown_seconds: u64,
+    /// `SESSION_REVOKE_LINK_TTL_SECONDS`.
+    pub session_revoke_link_ttl_seconds: u64,
+    /// `EXPORT_TTL_SECONDS`.
+    pub export_ttl_seconds: u64,
+    /// `EXPORT_COOLDOWN_SECONDS`.
+    pub export_cooldown_seconds: u64,
+}
+
+impl Default for TokensConfig {
+    fn default() -> Self {
+        TokensConfig {
+            verification_ttl_seconds: 24 * 60 * 60,
+            verification_resend_cooldown_seconds: 60,
+            email_login_link_ttl_seconds: 15 * 60,
+            email_login_code_ttl_seconds: 10 * 60,
+            email_login_max_code_attempts: 5,
+            unlock_link_ttl_seconds: 24 * 60 * 60,
+            unlock_email_cooldown_seconds: 60 * 60,
+            session_revoke_link_ttl_seconds: 7 * 24 * 60 * 60,
+            export_ttl_seconds: 24 * 60 * 60,
+            export_cooldown_seconds: 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockoutConfig {
+    /// Failed logins after which an account is locked out. `LOGIN_LOCKOUT_THRESHOLD`.
+    pub threshold: u32,
+    /// Failed logins after which an IP is locked out. `LOGIN_IP_LOCKOUT_THRESHOLD`.
+    pub ip_threshold: u32,
+    /// The first lockout, doubled with every further failure. `LOGIN_LOCKOUT_BASE_SECONDS`.
+    pub base_seconds: u64,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        LockoutConfig {
+            threshold: 5,
+            ip_threshold: 20,
+            base_seconds: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    /// `ACCOUNT_DELETION_GRACE_DAYS`.
+    pub deletion_grace_days: i64,
+    /// `ACCOUNT_PURGE_INTERVAL_SECONDS`.
+    pub purge_interval_seconds: u64,
+    /// `SUSPENSION_LIFT_INTERVAL_SECONDS`.
+    pub suspension_lift_interval_seconds: u64,
+    /// `USERNAME_CHANGE_COOLDOWN_DAYS`.
+    pub username_change_cooldown_days: i64,
+    /// How long an old username keeps pointing at its user. `USERNAME_REDIRECT_DAYS`.
+    pub username_redirect_days: i64,
+    /// Users that can be looked up at once. `USER_LOOKUP_LIMIT`.
+    pub lookup_limit: usize,
+}
+
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        AccountsConfig {
+            deletion_grace_days: 30,
+            purge_interval_seconds: 60 * 60,
+            suspension_lift_interval_seconds: 60,
+            username_change_cooldown_days: 30,
+            username_redirect_days: 90,
+            lookup_limit: 100,
+        }
+    }
+}
+
+/// Debug builds print emails instead of sending them, and do not need `url`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmtpConfig {
+    /// `SMTP_URL`.
+    pub url: Option<String>,
+    /// Required. `FROM_MAILBOX`.
+    pub from: String,
+}
+
+/// Debug builds accept any captcha, and do not need a secret.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptchaConfig {
+    /// `TURNSTILE_SECRET_KEY`.
+    pub turnstile_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AvatarStorage {
+    #[default]
+    Local,
+    S3,
+}
+
+impl FromStr for AvatarStorage {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(AvatarStorage::Local),
+            "s3" => Ok(AvatarStorage::S3),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AvatarsConfig {
+    /// `AVATAR_MAX_BYTES`.
+    pub max_bytes: usize,
+    /// `AVATAR_STORAGE`.
+    pub storage: AvatarStorage,
+    /// Where local avatars are kept. `AVATAR_LOCAL_DIR`.
+    pub local_dir: PathBuf,
+    /// Where local avatars are served. `AVATAR_BASE_URL`.
+    pub base_url: String,
+    pub s3: S3Config,
+}
+
+impl Default for AvatarsConfig {
+    fn default() -> Self {
+        AvatarsConfig {
+            max_bytes: 5 * 1024 * 1024,
+            storage: AvatarStorage::Local,
+            local_dir: PathBuf::from("./avatars"),
+            base_url: "/avatars".to_string(),
+            s3: S3Config::default(),
+        }
+    }
+}
+
+/// Credentials are read from the usual AWS environment variables.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    /// Required with S3 storage. `S3_BUCKET`.
+    pub bucket: String,
+    /// `S3_REGION`.
+    pub region: String,
+    /// For S3-compatible services other than AWS. `S3_ENDPOINT`.
+    pub endpoint: Option<String>,
+    /// Defaults to the bucket URL. `S3_PUBLIC_URL`.
+    pub public_url: Option<String>,
+    /// `S3_PATH_STYLE`.
+    pub path_style: bool,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            bucket: String::new(),
+            region: "us-east-1".to_string(),
+            endpoint: None,
+            public_url: None,
+            path_style: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeoIpConfig {
+    /// A MaxMind City database, without which sign-in alerts carry no location.
+    /// `GEOIP_DB_PATH`.
+    pub db_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// How long each readiness check may take. `HEALTH_TIMEOUT_MS`.
+    pub timeout_ms: u64,
+    /// Whether readiness also requires the SMTP server to accept connections.
+    /// `HEALTH_CHECK_SMTP`.
+    pub check_smtp: bool,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            timeout_ms: 2000,
+            check_smtp: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, including the fields of the spans it was logged in.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// `LOG_FORMAT`.
+    pub log_format: LogFormat,
+    /// An OTLP/HTTP traces endpoint such as `http://localhost:4318/v1/traces`, without
+    /// which spans are not exported. `OTLP_ENDPOINT`.
+    pub otlp_endpoint: Option<String>,
+    /// `OTEL_SERVICE_NAME`.
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            log_format: LogFormat::Text,
+            otlp_endpoint: None,
+            service_name: "websxz-accounts".to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// Every problem found, so that they can be fixed in one go.
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "failed to read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "failed to parse {}: {}", path.display(), e),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Reads the config file, applies the environment and validates the result.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut config = Self::read()?;
+
+        let mut problems = config.apply_env(&env::vars().collect());
+        problems.extend(config.validate());
+        if !problems.is_empty() {
+            return Err(ConfigError::Invalid(problems));
+        }
+
+        Ok(config)
+    }
+
+    /// Reads only the database URL, so that migrations can run before the rest of the
+    /// configuration is complete.
+    pub fn database_url() -> Result<String, ConfigError> {
+        let url = match env::var("DB_URL") {
+            Ok(url) => url,
+            Err(_) => Self::read()?.database.url,
+        };
+        if url.is_empty() {
+            return Err(ConfigError::Invalid(vec![
+                "database.url (DB_URL) must be set".to_string(),
+            ]));
+        }
+
+        Ok(url)
+    }
+
+    /// The config file, or the defaults without one.
+    fn read() -> Result<Self, ConfigError> {
+        match env::var("CONFIG_FILE") {
+            Ok(path) => Self::from_file(Path::new(&path)),
+            Err(_) if Path::new("config.toml").exists() => {
+                Self::from_file(Path::new("config.toml"))
+            }
+            Err(_) => Ok(Config::default()),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Overrides the config with the environment `vars`, returning the variables that
+    /// could not be parsed.
+    fn apply_env(&mut self, vars: &HashMap<String, String>) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut var = |key: &str, apply: &mut dyn FnMut(&str) -> bool| {
+            if let Some(value) = vars.get(key) {
+                if !apply(value) {
+                    problems.push(format!("{} has an invalid value {:?}", key, value));
+                }
+            }
+        };
+
+        let server = &mut self.server;
+        var("LISTEN_ADDR", &mut |v| parse(v, &mut server.listen));
+        var("PUBLIC_URL", &mut |v| parse(v, &mut server.public_url));
+        var("AUTO_MIGRATE", &mut |v| parse(v, &mut server.auto_migrate));
+        var("SHUTDOWN_DRAIN_SECONDS", &mut |v| parse(v, &mut server.drain_seconds));
+        var("SHUTDOWN_TIMEOUT_SECONDS", &mut |v| {
+            parse(v, &mut server.shutdown_timeout_seconds)
+        });
+        var("METRICS_LISTEN_ADDR", &mut |v| parse_some(v, &mut server.metrics_listen));
+        var("TRUSTED_PROXIES", &mut |v| parse(v, &mut server.trusted_proxies));
+
+        var("DB_URL", &mut |v| parse(v, &mut self.database.url));
+
+        let store = &mut self.store;
+        var("EPHEMERAL_STORE", &mut |v| parse(v, &mut store.backend));
+        var("REDIS_URL", &mut |v| parse_some(v, &mut store.redis_url));
+        var("REDIS_CONNECT_TIMEOUT_MS", &mut |v| parse(v, &mut store.connect_timeout_ms));
+        var("REDIS_RESPONSE_TIMEOUT_MS", &mut |v| parse(v, &mut store.response_timeout_ms));
+        var("REDIS_RECONNECT_RETRIES", &mut |v| parse(v, &mut store.reconnect_retries));
+        var("REDIS_RECONNECT_MAX_DELAY_MS", &mut |v| {
+            parse(v, &mut store.reconnect_max_delay_ms)
+        });
+
+        let auth = &mut self.auth;
+        var("JWT_SECRET", &mut |v| parse(v, &mut auth.jwt_secret));
+        var("ADMIN_UIDS", &mut |v| {
+            v.split(',')
+                .map(|uid| uid.trim().parse())
+                .collect::<Result<_, _>>()
+                .map(|uids| auth.admin_uids = uids)
+                .is_ok()
+        });
+        var("ACCESS_TOKEN_TTL_SECONDS", &mut |v| {
+            parse(v, &mut auth.access_token_ttl_seconds)
+        });
+        var("REFRESH_TOKEN_TTL_SECONDS", &mut |v| {
+            parse(v, &mut auth.refresh_token_ttl_seconds)
+        });
+        var("AUTHORIZATION_CODE_TTL_SECONDS", &mut |v| {
+            parse(v, &mut auth.authorization_code_ttl_seconds)
+        });
+
+        let tokens = &mut self.tokens;
+        var("VERIFICATION_TTL_SECONDS", &mut |v| {
+            parse(v, &mut tokens.verification_ttl_seconds)
+        });
+        var("VERIFICATION_RESEND_COOLDOWN_SECONDS", &mut |v| {
+            parse(v, &mut tokens.verification_resend_cooldown_seconds)
+        });
+        var("EMAIL_LOGIN_LINK_TTL_SECONDS", &mut |v| {
+            parse(v, &mut tokens.email_login_link_ttl_seconds)
+        });
+        var("EMAIL_LOGIN_CODE_TTL_SECONDS", &mut |v| {
+            parse(v, &mut tokens.email_login_code_ttl_seconds)
+        });
+        var("EMAIL_LOGIN_MAX_CODE_ATTEMPTS", &mut |v| {
+            parse(v, &mut tokens.email_login_max_code_attempts)
+        });
+        var("UNLOCK_LINK_TTL_SECONDS", &mut |v| {
+            parse(v, &mut tokens.unlock_link_ttl_seconds)
+        });
+        var("UNLOCK_EMAIL_COOLDOWN_SECONDS", &mut |v| {
+            parse(v, &mut tokens.unlock_email_cooldown_seconds)
+        });
+        var("SESSION_REVOKE_LINK_TTL_SECONDS", &mut |v| {
+            parse(v, &mut tokens.session_revoke_link_ttl_seconds)
+        });
+        var("EXPORT_TTL_SECONDS", &mut |v| parse(v, &mut tokens.export_ttl_seconds));
+        var("EXPORT_COOLDOWN_SECONDS", &mut |v| {
+            parse(v, &mut tokens.export_cooldown_seconds)
+        });
+
+        let lockout = &mut self.lockout;
+        var("LOGIN_LOCKOUT_THRESHOLD", &mut |v| parse(v, &mut lockout.threshold));
+        var("LOGIN_IP_LOCKOUT_THRESHOLD", &mut |v| parse(v, &mut lockout.ip_threshold));
+        var("LOGIN_LOCKOUT_BASE_SECONDS", &mut |v| parse(v, &mut lockout.base_seconds));
+
+        let accounts = &mut self.accounts;
+        var("ACCOUNT_DELETION_GRACE_DAYS", &mut |v| {
+            parse(v, &mut accounts.deletion_grace_days)
+        });
+        var("ACCOUNT_PURGE_INTERVAL_SECONDS", &mut |v| {
+            parse(v, &mut accounts.purge_interval_seconds)
+        });
+        var("SUSPENSION_LIFT_INTERVAL_SECONDS", &mut |v| {
+            parse(v, &mut accounts.suspension_lift_interval_seconds)
+        });
+        var("USERNAME_CHANGE_COOLDOWN_DAYS", &mut |v| {
+            parse(v, &mut accounts.username_change_cooldown_days)
+        });
+        var("USERNAME_REDIRECT_DAYS", &mut |v| {
+            parse(v, &mut accounts.username_redirect_days)
+        });
+        var("USER_LOOKUP_LIMIT", &mut |v| parse(v, &mut accounts.lookup_limit));
+
+        var("SMTP_URL", &mut |v| parse_some(v, &mut self.smtp.url));
+        var("FROM_MAILBOX", &mut |v| parse(v, &mut self.smtp.from));
+        var("TURNSTILE_SECRET_KEY", &mut |v| {
+            parse_some(v, &mut self.captcha.turnstile_secret)
+        });
+
+        let avatars = &mut self.avatars;
+        var("AVATAR_MAX_BYTES", &mut |v| parse(v, &mut avatars.max_bytes));
+        var("AVATAR_STORAGE", &mut |v| parse(v, &mut avatars.storage));
+        var("AVATAR_LOCAL_DIR", &mut |v| parse(v, &mut avatars.local_dir));
+        var("AVATAR_BASE_URL", &mut |v| parse(v, &mut avatars.base_url));
+        let s3 = &mut avatars.s3;
+        var("S3_BUCKET", &mut |v| parse(v, &mut s3.bucket));
+        var("S3_REGION", &mut |v| parse(v, &mut s3.region));
+        var("S3_ENDPOINT", &mut |v| parse_some(v, &mut s3.endpoint));
+        var("S3_PUBLIC_URL", &mut |v| parse_some(v, &mut s3.public_url));
+        var("S3_PATH_STYLE", &mut |v| parse(v, &mut s3.path_style));
+
+        var("GEOIP_DB_PATH", &mut |v| parse_some(v, &mut self.geoip.db_path));
+
+        var("HEALTH_TIMEOUT_MS", &mut |v| parse(v, &mut self.health.timeout_ms));
+        var("HEALTH_CHECK_SMTP", &mut |v| parse(v, &mut self.health.check_smtp));
+
+        let telemetry = &mut self.telemetry;
+        var("LOG_FORMAT", &mut |v| parse(v, &mut telemetry.log_format));
+        var("OTLP_ENDPOINT", &mut |v| parse_some(v, &mut telemetry.otlp_endpoint));
+        var("OTEL_SERVICE_NAME", &mut |v| parse(v, &mut telemetry.service_name));
+
+        for (key, value) in vars {
+            if let Some(name) = key.strip_prefix("RATE_LIMIT_") {
+                self.rate_limits.insert(name.to_lowercase(), value.clone());
+            }
+        }
+
+        problems
+    }
+
+    /// Checks everything that could otherwise only fail once the server is running.
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut check = |ok: bool, problem: &str| {
+            if !ok {
+                problems.push(problem.to_string());
+            }
+        };
+
+        check(
+            Url::parse(&self.server.public_url)
+                .is_ok_and(|url| matches!(url.scheme(), "http" | "https")),
+            "server.public_url (PUBLIC_URL) must be an http or https URL",
+        );
+        check(
+            !self.database.url.is_empty(),
+            "database.url (DB_URL) must be set",
+        );
+        if self.store.backend == StoreBackend::Redis {
+            check(
+                self.store
+                    .redis_url
+                    .as_deref()
+                    .is_some_and(|url| url.into_connection_info().is_ok()),
+                "store.redis_url (REDIS_URL) must be a valid Redis URL with the redis backend",
+            );
+        }
+
+        check(
+            !self.auth.jwt_secret.is_empty(),
+            "auth.jwt_secret (JWT_SECRET) must be set",
+        );
+        check(
+            self.auth.access_token_ttl_seconds > 0
+                && self.auth.refresh_token_ttl_seconds > 0
+                && self.auth.authorization_code_ttl_seconds > 0,
+            "auth token lifetimes must be positive",
+        );
+        check(
+            self.tokens.email_login_max_code_attempts > 0,
+            "tokens.email_login_max_code_attempts must be positive",
+        );
+        check(
+            self.lockout.threshold > 0 && self.lockout.ip_threshold > 0,
+            "lockout thresholds must be positive",
+        );
+        check(
+            self.accounts.purge_interval_seconds > 0
+                && self.accounts.suspension_lift_interval_seconds > 0,
+            "accounts intervals must be positive",
+        );
+        check(
+            self.accounts.deletion_grace_days >= 0
+                && self.accounts.username_change_cooldown_days >= 0
+                && self.accounts.username_redirect_days >= 0,
+            "accounts day counts must not be negative",
+        );
+        check(
+            self.accounts.lookup_limit > 0,
+            "accounts.lookup_limit (USER_LOOKUP_LIMIT) must be positive",
+        );
+
+        check(
+            self.smtp.from.parse::<Mailbox>().is_ok(),
+            "smtp.from (FROM_MAILBOX) must be a mailbox such as `WebSxz <noreply@websxz.org>`",
+        );
+        if !cfg!(debug_assertions) {
+            check(
+                self.smtp
+                    .url
+                    .as_deref()
+                    .is_some_and(|url| SmtpTransport::from_url(url).is_ok()),
+                "smtp.url (SMTP_URL) must be a valid SMTP URL",
+            );
+            check(
+                self.captcha.turnstile_secret.is_some(),
+                "captcha.turnstile_secret (TURNSTILE_SECRET_KEY) must be set",
+            );
+        }
+
+        if self.avatars.storage == AvatarStorage::S3 {
+            let s3 = &self.avatars.s3;
+            check(
+                !s3.bucket.is_empty(),
+                "avatars.s3.bucket (S3_BUCKET) must be set with s3 storage",
+            );
+            check(
+                s3.endpoint.is_some() || s3.region.parse::<s3::Region>().is_ok(),
+                "avatars.s3.region (S3_REGION) must be a known region unless an endpoint is set",
+            );
+        }
+
+        if let Some(path) = &self.geoip.db_path {
+            check(
+                path.is_file(),
+                "geoip.db_path (GEOIP_DB_PATH) must point to a database file",
+            );
+        }
+
+        check(
+            self.health.timeout_ms > 0,
+            "health.timeout_ms (HEALTH_TIMEOUT_MS) must be positive",
+        );
+
+        if let Some(endpoint) = &self.telemetry.otlp_endpoint {
+            check(
+                Url::parse(endpoint).is_ok_and(|url| matches!(url.scheme(), "http" | "https")),
+                "telemetry.otlp_endpoint (OTLP_ENDPOINT) must be an http or https URL",
+            );
+        }
+
+        for (name, algorithm) in &self.rate_limits {
+            if !rate_limit::NAMES.contains(&name.as_str()) {
+                problems.push(format!(
+                    "unknown rate limit {}, expected one of: {}",
+                    name,
+                    rate_limit::NAMES.join(", ")
+                ));
+            }
+            if Algorithm::parse(algorithm).is_none() {
+                problems.push(format!(
+                    "rate limit {} must be a known algorithm with positive numbers: {:?}",
+                    name, algorithm
+                ));
+            }
+        }
+
+        problems
+    }
+}
+
+fn parse<T: FromStr>(value: &str, target: &mut T) -> bool {
+    value.parse().map(|value| *target = value).is_ok()
+}
+
+fn parse_some<T: FromStr>(value: &str, target: &mut Option<T>) -> bool {
+    value.parse().map(|value| *target = Some(value)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Config {
+        toml::from_str(include_str!("../config.example.toml")).unwrap()
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn problems(config: &Config, field: &str) -> usize {
+        config
+            .validate()
+            .iter()
+            .filter(|problem| problem.contains(field))
+            .count()
+    }
+
+    #[test]
+    fn the_example_is_complete() {
+        let config = example();
+
+        assert_eq!(config.accounts.lookup_limit, 100);
+        assert_eq!(config.store.backend, StoreBackend::Redis);
+        assert!(toml::from_str::<Config>("[server]\nunknown = 1").is_err());
+    }
+
+    #[test]
+    fn the_environment_takes_precedence() {
+        let mut config = example();
+        let problems = config.apply_env(&vars(&[
+            ("USER_LOOKUP_LIMIT", "10"),
+            ("ADMIN_UIDS", "1, 2"),
+            ("OTLP_ENDPOINT", "http://localhost:4318/v1/traces"),
+            ("RATE_LIMIT_LOGIN", "sliding_window:10:600"),
+        ]));
+
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(config.accounts.lookup_limit, 10);
+        assert_eq!(config.auth.admin_uids, [1, 2]);
+        assert!(config.telemetry.otlp_endpoint.is_some());
+        assert_eq!(config.rate_limits["login"], "sliding_window:10:600");
+        assert_eq!(config.accounts.deletion_grace_days, 30);
+    }
+
+    #[test]
+    fn unparsable_variables_are_reported() {
+        let mut config = example();
+        let problems = config.apply_env(&vars(&[
+            ("USER_LOOKUP_LIMIT", "-1"),
+            ("ADMIN_UIDS", "1,two"),
+            ("LISTEN_ADDR", "8080"),
+        ]));
+
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert_eq!(config.accounts.lookup_limit, 100);
+        assert!(config.auth.admin_uids.is_empty());
+    }
+
+    #[test]
+    fn rejects_impossible_values() {
+        let mut config = example();
+        assert_eq!(problems(&config, "lookup_limit"), 0);
+
+        config.accounts.deletion_grace_days = -1;
+        config.accounts.lookup_limit = 0;
+        config.auth.authorization_code_ttl_seconds = 0;
+        config.rate_limits = HashMap::from([
+            ("login".to_string(), "sliding_window:10:0".to_string()),
+            ("users".to_string(), "token_bucket:30:0".to_string()),
+            ("logins".to_string(), "sliding_window:10:600".to_string()),
+        ]);
+
+        assert_eq!(problems(&config, "day counts"), 1);
+        assert_eq!(problems(&config, "lookup_limit"), 1);
+        assert_eq!(problems(&config, "token lifetimes"), 1);
+        assert_eq!(problems(&config, "rate limit"), 3);
+        assert_eq!(problems(&config, "unknown rate limit logins"), 1);
+    }
+}
@@ -1,7 +1,12 @@
 #![allow(long_running_const_eval)]
 
+use std::sync::Arc;
+
 use crate::data::error::Error;
+use crate::entity::user::{self, Role};
 use crate::utils::db::StanderizeError;
+use crate::utils::suspension;
+use crate::AppState;
 use axum::extract::FromRequestParts;
 use axum::http::request::Parts;
 use axum::{async_trait, RequestPartsExt};
@@ -12,39 +17,51 @@ use axum_extra::{
 use chrono::Utc;
 use jsonwebtoken::errors::ErrorKind;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use lazy_static::lazy_static;
+use sea_orm::EntityTrait;
 use serde::{Deserialize, Serialize};
 
-lazy_static! {
-    static ref TOKEN_KEYS: Keys = {
-        let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-        Keys::new(secret.as_bytes())
-    };
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims<const S: u16> {
     pub exp: usize,
-    pub uid: u32,
+    pub uid: i32,
     pub scopes: Option<Vec<Scope>>,
+    /// The session the token was issued for, absent for OAuth tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Scope {
     #[serde(rename = "profile.read")]
     ProfileRead,
     #[serde(rename = "profile.write")]
     ProfileWrite,
+    #[serde(rename = "sessions.read")]
+    SessionsRead,
+    #[serde(rename = "sessions.write")]
+    SessionsWrite,
+    #[serde(rename = "activity.read")]
+    ActivityRead,
+    #[serde(rename = "profile.details")]
+    ProfileDetails,
+    #[serde(rename = "users.read")]
+    UsersRead,
+    /// Lets a token act with the administrative permissions of its user's role. Only
+    /// official clients are granted it.
+    #[serde(rename = "admin")]
+    Admin,
 }
 
+/// Besides checking the token and its scopes, rejects tokens of suspended users so that
+/// a suspension takes effect immediately. That check fails open if Redis is unavailable.
 #[async_trait]
-impl<T, const S: u16> FromRequestParts<T> for Claims<S>
-where
-    T: Send + Sync,
-{
+impl<const S: u16> FromRequestParts<Arc<AppState>> for Claims<S> {
     type Rejection = Error;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &T) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
         // Extract the token from the authorization header
         let TypedHeader(Authorization(bearer)) = parts
             .extract::<TypedHeader<Authorization<Bearer>>>()
@@ -55,7 +72,7 @@ where
             })?;
         // Decode the user data
         let token_data =
-            decode::<Claims<S>>(bearer.token(), &TOKEN_KEYS.decoding, &Validation::default())
+            decode::<Claims<S>>(bearer.token(), &state.keys.decoding, &Validation::default())
                 .map_err(|e| {
                     tracing::debug!("{}", e);
                     match e.kind() {
@@ -63,6 +80,7 @@ where
                         _ => Error::InvalidToken,
                     }
                 })?;
+        tracing::Span::current().record("user_id", token_data.claims.uid);
 
         if let Some(s) = &token_data.claims.scopes {
             if S != S & scopes(s.as_slice()) {
@@ -70,10 +88,92 @@ where
             }
         }
 
+        if let Err(e @ Error::AccountSuspended { .. }) =
+            suspension::check_cached(state.store.as_ref(), token_data.claims.uid).await
+        {
+            return Err(e);
+        }
+
         Ok(token_data.claims)
     }
 }
 
+/// Administrative actions, granted through [`Role`]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ViewUsers,
+    EditUsers,
+    ManageRoles,
+    VerifyEmails,
+    RevokeSessions,
+    SuspendUsers,
+    ViewAudit,
+    ViewMetrics,
+}
+
+impl Permission {
+    pub fn granted_to(self, role: Role) -> bool {
+        match role {
+            Role::Admin => true,
+            Role::Moderator => matches!(
+                self,
+                Permission::ViewUsers
+                    | Permission::RevokeSessions
+                    | Permission::SuspendUsers
+                    | Permission::ViewAudit
+            ),
+            Role::User => false,
+        }
+    }
+}
+
+/// A token with the `admin` scope whose user has a role other than [`Role::User`].
+///
+/// Users listed in `auth.admin_uids` are always admins, so that the first one can be set up.
+#[derive(Debug)]
+pub struct Admin {
+    pub claims: Claims<{ scopes(&[Scope::Admin]) }>,
+    pub role: Role,
+}
+
+impl Admin {
+    pub fn require(&self, permission: Permission) -> Result<(), Error> {
+        if permission.granted_to(self.role) {
+            Ok(())
+        } else {
+            Err(Error::Forbidden)
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Admin {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state).await?;
+
+        let role = if state.config.auth.admin_uids.contains(&claims.uid) {
+            Role::Admin
+        } else {
+            user::Entity::find_by_id(claims.uid)
+                .one(&state.db)
+                .await
+                .warn_err()?
+                .ok_or(Error::Forbidden)?
+                .role
+        };
+        if role == Role::User {
+            return Err(Error::Forbidden);
+        }
+
+        Ok(Admin { claims, role })
+    }
+}
+
 pub const fn scopes(s: &[Scope]) -> u16 {
     let mut result = 0;
     let mut i = 0;
@@ -87,40 +187,56 @@ pub const fn scopes(s: &[Scope]) -> u16 {
     result
 }
 
-pub fn generate_token(uid: u32) -> Result<String, Error> {
-    Ok(encode(
+pub fn generate_token(state: &AppState, uid: i32, sid: &str) -> Result<String, Error> {
+    encode(
         &Header::default(),
         &Claims::<0> {
             uid,
-            exp: Utc::now().timestamp() as usize + 30 * 60,
+            exp: expiry(state),
             scopes: None,
+            sid: Some(sid.to_string()),
         },
-        &TOKEN_KEYS.encoding,
+        &state.keys.encoding,
     )
     .map_err(|_| {
         tracing::warn!("failed to generate a token for id: {}", uid);
         Error::InternalServerError
-    })?)
+    })
+}
+
+/// Returns the user id of a valid access token without checking any scope.
+pub fn decode_uid(keys: &Keys, token: &str) -> Option<i32> {
+    decode::<Claims<0>>(token, &keys.decoding, &Validation::default())
+        .ok()
+        .map(|data| data.claims.uid)
 }
 
-pub fn generate_oauth_token(uid: u32, s: Vec<Scope>) -> Result<String, Error> {
-    Ok(
-        encode(&Header::default(), &Claims::<0>{
+pub fn generate_oauth_token(state: &AppState, uid: i32, s: Vec<Scope>) -> Result<String, Error> {
+    encode(
+        &Header::default(),
+        &Claims::<0> {
             uid,
-            exp: Utc::now().timestamp() as usize + 30 * 60,
+            exp: expiry(state),
             scopes: Some(s),
-        }, &TOKEN_KEYS.encoding)
-        .warn_err()?
+            sid: None,
+        },
+        &state.keys.encoding,
     )
+    .warn_err()
+}
+
+fn expiry(state: &AppState) -> usize {
+    Utc::now().timestamp() as usize + state.config.auth.access_token_ttl_seconds as usize
 }
 
-struct Keys {
+/// The keys access tokens are signed and verified with.
+pub struct Keys {
     encoding: EncodingKey,
     decoding: DecodingKey,
 }
 
 impl Keys {
-    fn new(secret: &[u8]) -> Self {
+    pub fn new(secret: &[u8]) -> Self {
         Self {
             encoding: EncodingKey::from_secret(secret),
             decoding: DecodingKey::from_secret(secret),
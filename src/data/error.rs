@@ -1,50 +1,385 @@
-use axum::http::StatusCode;
-use axum::{
-    response::{IntoResponse, Response},
-    Json,
-};
+use std::borrow::Cow;
+
+use axum::body::Body;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::NaiveDateTime;
 use serde::Serialize;
-use serde_json::json;
+use serde_json::Value;
+use utoipa::ToSchema;
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::data::locale::Locale;
 
-#[derive(Serialize, Debug)]
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Responds with an RFC 7807 problem document whose `code` names the variant.
+///
+/// The response carries the error as an extension, so that
+/// [`LocalizeErrorsLayer`](crate::middleware::problem::LocalizeErrorsLayer) can render it
+/// again in the language of the client and with the request id. Without it, it is in
+/// English.
+#[derive(Debug, Clone)]
 pub enum Error {
     TimeOutOrDuplicateCaptcha,
     InvalidCaptcha,
     InternalServerError,
     BadRequest,
+    /// A request body or query that failed validation, listed per field.
+    InvalidFields(ValidationErrors),
     NotFound,
     MissingCaptchaToken,
     IncorrectEmailOrPassword,
     Unauthorized,
     RegisteredEmail,
+    UsernameTaken,
     InvalidToken,
     ExpiredToken,
     MissingScope,
+    Forbidden,
+    IncorrectCode,
+    UnsupportedImage,
+    TooManyAttempts { retry_after: u64 },
+    RateLimited { retry_after: u64 },
+    /// `until` is absent for permanent bans.
+    AccountSuspended {
+        reason: String,
+        until: Option<NaiveDateTime>,
+    },
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> Response {
-        let status_code = match &self {
-            Error::TimeOutOrDuplicateCaptcha => StatusCode::BAD_REQUEST,
-            Error::InvalidCaptcha => StatusCode::BAD_REQUEST,
-            Error::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
-            Error::BadRequest => StatusCode::BAD_REQUEST,
-            Error::NotFound => StatusCode::NOT_FOUND,
-            Error::MissingCaptchaToken => StatusCode::BAD_REQUEST,
-            Error::IncorrectEmailOrPassword => StatusCode::UNAUTHORIZED,
-            Error::Unauthorized => StatusCode::UNAUTHORIZED,
-            Error::RegisteredEmail => StatusCode::CONFLICT,
-            Error::InvalidToken => StatusCode::BAD_REQUEST,
-            Error::ExpiredToken => StatusCode::UNAUTHORIZED,
-            Error::MissingScope => StatusCode::FORBIDDEN,
+impl From<ValidationErrors> for Error {
+    fn from(errors: ValidationErrors) -> Self {
+        Error::InvalidFields(errors)
+    }
+}
+
+/// The stable, machine-readable `code` of a problem, one for each [`Error`] variant.
+#[derive(Serialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    TimeOutOrDuplicateCaptcha,
+    InvalidCaptcha,
+    InternalServerError,
+    BadRequest,
+    InvalidFields,
+    NotFound,
+    MissingCaptchaToken,
+    IncorrectEmailOrPassword,
+    Unauthorized,
+    RegisteredEmail,
+    UsernameTaken,
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    Forbidden,
+    IncorrectCode,
+    UnsupportedImage,
+    TooManyAttempts,
+    RateLimited,
+    AccountSuspended,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::TimeOutOrDuplicateCaptcha => StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidCaptcha => StatusCode::BAD_REQUEST,
+            ErrorCode::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidFields => StatusCode::BAD_REQUEST,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::MissingCaptchaToken => StatusCode::BAD_REQUEST,
+            ErrorCode::IncorrectEmailOrPassword => StatusCode::UNAUTHORIZED,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::RegisteredEmail => StatusCode::CONFLICT,
+            ErrorCode::UsernameTaken => StatusCode::CONFLICT,
+            ErrorCode::InvalidToken => StatusCode::BAD_REQUEST,
+            ErrorCode::ExpiredToken => StatusCode::UNAUTHORIZED,
+            ErrorCode::MissingScope => StatusCode::FORBIDDEN,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::IncorrectCode => StatusCode::UNAUTHORIZED,
+            ErrorCode::UnsupportedImage => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::TooManyAttempts => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::AccountSuspended => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::TimeOutOrDuplicateCaptcha => ErrorCode::TimeOutOrDuplicateCaptcha,
+            Error::InvalidCaptcha => ErrorCode::InvalidCaptcha,
+            Error::InternalServerError => ErrorCode::InternalServerError,
+            Error::BadRequest => ErrorCode::BadRequest,
+            Error::InvalidFields(_) => ErrorCode::InvalidFields,
+            Error::NotFound => ErrorCode::NotFound,
+            Error::MissingCaptchaToken => ErrorCode::MissingCaptchaToken,
+            Error::IncorrectEmailOrPassword => ErrorCode::IncorrectEmailOrPassword,
+            Error::Unauthorized => ErrorCode::Unauthorized,
+            Error::RegisteredEmail => ErrorCode::RegisteredEmail,
+            Error::UsernameTaken => ErrorCode::UsernameTaken,
+            Error::InvalidToken => ErrorCode::InvalidToken,
+            Error::ExpiredToken => ErrorCode::ExpiredToken,
+            Error::MissingScope => ErrorCode::MissingScope,
+            Error::Forbidden => ErrorCode::Forbidden,
+            Error::IncorrectCode => ErrorCode::IncorrectCode,
+            Error::UnsupportedImage => ErrorCode::UnsupportedImage,
+            Error::TooManyAttempts { .. } => ErrorCode::TooManyAttempts,
+            Error::RateLimited { .. } => ErrorCode::RateLimited,
+            Error::AccountSuspended { .. } => ErrorCode::AccountSuspended,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.code().status()
+    }
+
+    /// A sentence describing the error to the user.
+    pub fn message(&self, locale: Locale) -> String {
+        let text = match self {
+            Error::TimeOutOrDuplicateCaptcha => locale.pick(
+                "The captcha has expired or was already used.",
+                "人机验证已过期或已被使用。",
+            ),
+            Error::InvalidCaptcha => locale.pick("The captcha is invalid.", "人机验证无效。"),
+            Error::InternalServerError => locale.pick(
+                "Something went wrong on our side. Please try again later.",
+                "服务器出现错误，请稍后重试。",
+            ),
+            Error::BadRequest => locale.pick("The request is malformed.", "请求格式有误。"),
+            Error::InvalidFields(_) => locale.pick("Some fields are invalid.", "部分字段无效。"),
+            Error::NotFound => locale.pick(
+                "The requested resource does not exist.",
+                "请求的资源不存在。",
+            ),
+            Error::MissingCaptchaToken => {
+                locale.pick("A captcha is required.", "请先完成人机验证。")
+            }
+            Error::IncorrectEmailOrPassword => {
+                locale.pick("Incorrect email or password.", "邮箱或密码错误。")
+            }
+            Error::Unauthorized => locale.pick("Authentication failed.", "身份验证失败。"),
+            Error::RegisteredEmail => {
+                locale.pick("This email is already registered.", "该邮箱已被注册。")
+            }
+            Error::UsernameTaken => locale.pick("This username is taken.", "该用户名已被占用。"),
+            Error::InvalidToken => locale.pick("The token is invalid.", "令牌无效。"),
+            Error::ExpiredToken => locale.pick("The token has expired.", "令牌已过期。"),
+            Error::MissingScope => locale.pick(
+                "The token was not granted the scope this requires.",
+                "令牌未被授予所需的权限范围。",
+            ),
+            Error::Forbidden => locale.pick(
+                "You do not have permission to do this.",
+                "你没有执行此操作的权限。",
+            ),
+            Error::IncorrectCode => locale.pick("The code is incorrect.", "验证码错误。"),
+            Error::UnsupportedImage => {
+                locale.pick("This image format is not supported.", "不支持该图片格式。")
+            }
+            Error::TooManyAttempts { retry_after } => {
+                return locale.pick(
+                    format!("Too many attempts. Try again in {} seconds.", retry_after),
+                    format!("尝试次数过多，请在 {} 秒后重试。", retry_after),
+                )
+            }
+            Error::RateLimited { retry_after } => {
+                return locale.pick(
+                    format!("Too many requests. Try again in {} seconds.", retry_after),
+                    format!("请求过于频繁，请在 {} 秒后重试。", retry_after),
+                )
+            }
+            Error::AccountSuspended {
+                reason,
+                until: Some(until),
+            } => {
+                return locale.pick(
+                    format!("This account is suspended until {} UTC: {}", until, reason),
+                    format!("该账号已被停用至 {}（UTC）：{}", until, reason),
+                )
+            }
+            Error::AccountSuspended {
+                reason,
+                until: None,
+            } => {
+                return locale.pick(
+                    format!("This account is permanently suspended: {}", reason),
+                    format!("该账号已被永久停用：{}", reason),
+                )
+            }
         };
 
-        (
-            status_code,
-            Json(json!({
-                "error": self
+        text.to_string()
+    }
+
+    /// The problem document describing this error.
+    pub fn problem(&self, locale: Locale, request_id: Option<&str>) -> Problem {
+        let status = self.status();
+        let mut problem = Problem {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or_default(),
+            status: status.as_u16(),
+            detail: self.message(locale),
+            code: self.code(),
+            request_id: request_id.map(str::to_string),
+            errors: Vec::new(),
+            retry_after: None,
+            reason: None,
+            until: None,
+        };
+
+        match self {
+            Error::InvalidFields(errors) => field_errors(errors, "", locale, &mut problem.errors),
+            Error::TooManyAttempts { retry_after } | Error::RateLimited { retry_after } => {
+                problem.retry_after = Some(*retry_after)
+            }
+            Error::AccountSuspended { reason, until } => {
+                problem.reason = Some(reason.clone());
+                problem.until = *until;
+            }
+            _ => {}
+        }
+
+        problem
+    }
+}
+
+/// An RFC 7807 problem document, with the `code` of the error and the data it carries as
+/// extension members.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Problem {
+    /// Always `about:blank`, as the `code` tells problems apart.
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    code: ErrorCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    until: Option<NaiveDateTime>,
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let body = serde_json::to_vec(&self).expect("problems serialize");
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = StatusCode::from_u16(self.status).unwrap_or_default();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+
+        response
+    }
+}
+
+/// Why a single field was rejected. Nested fields are named by their path, such as
+/// `visibility.bio` or `items[0].name`.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct FieldError {
+    field: String,
+    /// The check that failed, such as `length`, `email` or `charset`.
+    #[schema(value_type = String)]
+    code: Cow<'static, str>,
+    message: String,
+}
+
+fn field_errors(errors: &ValidationErrors, path: &str, locale: Locale, out: &mut Vec<FieldError>) {
+    let mut fields: Vec<_> = errors.errors().iter().collect();
+    fields.sort_by_key(|(field, _)| *field);
+
+    for (field, kind) in fields {
+        let path = if path.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", path, field)
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => out.extend(errors.iter().map(|error| {
+                FieldError {
+                    field: path.clone(),
+                    code: error.code.clone(),
+                    message: field_message(error, locale),
+                }
             })),
-        )
-            .into_response()
+            ValidationErrorsKind::Struct(errors) => field_errors(errors, &path, locale, out),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    field_errors(errors, &format!("{}[{}]", path, index), locale, out);
+                }
+            }
+        }
+    }
+}
+
+/// Describes what a field has to be, by the code of the check it failed.
+fn field_message(error: &ValidationError, locale: Locale) -> String {
+    let param = |name: &str| error.params.get(name).and_then(Value::as_u64);
+
+    let text = match error.code.as_ref() {
+        "length" => {
+            return match (param("min"), param("max")) {
+                (Some(min), Some(max)) => locale.pick(
+                    format!("Must be {} to {} characters long.", min, max),
+                    format!("长度须在 {} 到 {} 个字符之间。", min, max),
+                ),
+                (Some(min), None) => locale.pick(
+                    format!("Must be at least {} characters long.", min),
+                    format!("长度须至少为 {} 个字符。", min),
+                ),
+                (None, Some(max)) => locale.pick(
+                    format!("Must be at most {} characters long.", max),
+                    format!("长度须至多为 {} 个字符。", max),
+                ),
+                (None, None) => locale.pick("Has an invalid length.", "长度不符合要求。").into(),
+            }
+        }
+        "email" => locale.pick("Must be an email address.", "须为有效的邮箱地址。"),
+        "url" => locale.pick("Must be a URL.", "须为有效的网址。"),
+        "charset" => locale.pick(
+            "May only contain lowercase letters, digits and underscores, and must start \
+             with a letter.",
+            "只能包含小写字母、数字和下划线，且须以字母开头。",
+        ),
+        "reserved" => locale.pick("Is reserved.", "为保留名称。"),
+        "locale" => locale.pick(
+            "Must be a language tag such as en-US.",
+            "须为语言标签，如 zh-CN。",
+        ),
+        "timezone" => locale.pick(
+            "Must be a time zone such as Asia/Shanghai.",
+            "须为时区，如 Asia/Shanghai。",
+        ),
+        "birthday" => locale.pick(
+            "Must be a date between 1900 and today.",
+            "须为 1900 年至今天之间的日期。",
+        ),
+        _ => locale.pick("Is invalid.", "无效。"),
+    };
+
+    text.to_string()
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let mut response = self.problem(Locale::default(), None).into_response();
+
+        if let Error::TooManyAttempts { retry_after } | Error::RateLimited { retry_after } = &self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, (*retry_after).into());
+        }
+        response.extensions_mut().insert(self);
+
+        response
     }
 }
@@ -0,0 +1,57 @@
+use axum::http::{header, HeaderMap};
+
+/// The languages responses can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    Zh,
+}
+
+impl Locale {
+    fn from_language(language: &str) -> Option<Self> {
+        match language {
+            "en" => Some(Locale::En),
+            "zh" => Some(Locale::Zh),
+            _ => None,
+        }
+    }
+
+    /// Picks the supported language the client prefers most according to
+    /// `Accept-Language`, ignoring regions.
+    pub fn negotiate(headers: &HeaderMap) -> Self {
+        let Some(accepted) = headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return Locale::default();
+        };
+
+        let mut best: Option<(Locale, f32)> = None;
+        for range in accepted.split(',') {
+            let mut params = range.split(';');
+            let tag = params.next().unwrap_or_default().trim();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let language = tag.split('-').next().unwrap_or_default().to_ascii_lowercase();
+
+            if let Some(locale) = Locale::from_language(&language) {
+                if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+                    best = Some((locale, quality));
+                }
+            }
+        }
+
+        best.map(|(locale, _)| locale).unwrap_or_default()
+    }
+
+    /// Picks the text for this locale out of its translations.
+    pub fn pick<T>(self, en: T, zh: T) -> T {
+        match self {
+            Locale::En => en,
+            Locale::Zh => zh,
+        }
+    }
+}
@@ -1,2 +1,5 @@
 pub mod error;
-pub mod credential;
\ No newline at end of file
+pub mod credential;
+pub mod locale;
+pub mod session;
+pub mod profile;
\ No newline at end of file
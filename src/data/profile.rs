@@ -0,0 +1,97 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::entity::user;
+
+/// Who may see a profile field. Ordered from most to least restrictive.
+#[derive(
+    Serialize, Deserialize, ToSchema, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum Visibility {
+    /// Only the user themselves.
+    #[default]
+    #[serde(rename = "private")]
+    Private,
+    /// OAuth clients granted the `profile.details` scope.
+    #[serde(rename = "clients")]
+    Clients,
+    /// Anyone, including the public profile endpoint.
+    #[serde(rename = "public")]
+    Public,
+}
+
+/// The visibility of every extended profile field, stored in `user.profile_visibility`.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy, Default)]
+#[serde(default)]
+pub struct FieldVisibility {
+    pub bio: Visibility,
+    pub website: Visibility,
+    pub locale: Visibility,
+    pub timezone: Visibility,
+    pub pronouns: Visibility,
+    pub birthday: Visibility,
+}
+
+impl FieldVisibility {
+    pub fn of(user: &user::Model) -> Self {
+        serde_json::from_value(user.profile_visibility.clone()).unwrap_or_default()
+    }
+}
+
+/// Who is looking at a profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Viewer {
+    Owner,
+    Client,
+    Public,
+}
+
+impl Viewer {
+    fn can_see(self, visibility: Visibility) -> bool {
+        match self {
+            Viewer::Owner => true,
+            Viewer::Client => visibility >= Visibility::Clients,
+            Viewer::Public => visibility == Visibility::Public,
+        }
+    }
+}
+
+/// The extended profile fields of a user, as far as a [`Viewer`] may see them.
+#[derive(Serialize, ToSchema, Debug, Clone, Default)]
+pub struct ProfileDetails {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub website: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pronouns: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub birthday: Option<NaiveDate>,
+}
+
+impl ProfileDetails {
+    pub fn new(user: &user::Model, viewer: Viewer) -> Self {
+        let visibility = FieldVisibility::of(user);
+        let show = |v, field: &Option<String>| {
+            if viewer.can_see(v) {
+                field.clone()
+            } else {
+                None
+            }
+        };
+
+        ProfileDetails {
+            bio: show(visibility.bio, &user.bio),
+            website: show(visibility.website, &user.website),
+            locale: show(visibility.locale, &user.locale),
+            timezone: show(visibility.timezone, &user.timezone),
+            pronouns: show(visibility.pronouns, &user.pronouns),
+            birthday: user.birthday.filter(|_| viewer.can_see(visibility.birthday)),
+        }
+    }
+}
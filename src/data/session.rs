@@ -0,0 +1,176 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{header, Extensions, HeaderMap};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+/// Information about the client a request comes from.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    /// The address found by [`client_ip`].
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn new(headers: &HeaderMap, extensions: &Extensions, trusted_proxies: usize) -> Self {
+        let ip = client_ip(headers, extensions, trusted_proxies).map(|ip| ip.to_string());
+        let user_agent = headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string);
+
+        Self { ip, user_agent }
+    }
+
+    /// A human readable name such as "Firefox on Windows" derived from the user agent.
+    pub fn device_name(&self) -> String {
+        let Some(ua) = self.user_agent.as_deref() else {
+            return "Unknown device".to_string();
+        };
+
+        let browser = if ua.contains("Edg/") {
+            "Edge"
+        } else if ua.contains("OPR/") {
+            "Opera"
+        } else if ua.contains("Firefox/") {
+            "Firefox"
+        } else if ua.contains("Chrome/") {
+            "Chrome"
+        } else if ua.contains("Safari/") {
+            "Safari"
+        } else {
+            "Unknown browser"
+        };
+
+        let os = if ua.contains("Android") {
+            "Android"
+        } else if ua.contains("iPhone") || ua.contains("iPad") {
+            "iOS"
+        } else if ua.contains("Windows") {
+            "Windows"
+        } else if ua.contains("Mac OS X") {
+            "macOS"
+        } else if ua.contains("Linux") {
+            "Linux"
+        } else {
+            "unknown OS"
+        };
+
+        format!("{} on {}", browser, os)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self::new(
+            &parts.headers,
+            &parts.extensions,
+            state.config.server.trusted_proxies,
+        ))
+    }
+}
+
+/// The address of the client, which only the proxies in front of this server can vouch
+/// for.
+///
+/// Without trusted proxies, that is the peer of the connection. Behind `trusted_proxies`
+/// of them, it is the entry that many places from the right of `X-Forwarded-For`, as
+/// every proxy appends the address it was connected from. Entries further left are
+/// chosen by the client and are ignored.
+pub fn client_ip(
+    headers: &HeaderMap,
+    extensions: &Extensions,
+    trusted_proxies: usize,
+) -> Option<IpAddr> {
+    let peer = || {
+        extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+    };
+    if trusted_proxies == 0 {
+        return peer();
+    }
+
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+
+    forwarded
+        .len()
+        .checked_sub(trusted_proxies)
+        .and_then(|index| forwarded[index].parse().ok())
+        .or_else(peer)
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Session {
+    pub id: String,
+    pub name: String,
+    pub created_at: i64,
+    pub last_used_at: i64,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub current: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(forwarded: &[&str], peer: &str) -> (HeaderMap, Extensions) {
+        let mut headers = HeaderMap::new();
+        for value in forwarded {
+            headers.append("x-forwarded-for", value.parse().unwrap());
+        }
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+
+        (headers, extensions)
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn ignores_forwarded_for_without_trusted_proxies() {
+        let (headers, extensions) = request(&["1.2.3.4"], "10.0.0.1:5000");
+
+        assert_eq!(client_ip(&headers, &extensions, 0), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn takes_the_entry_appended_by_the_outermost_trusted_proxy() {
+        let (headers, extensions) = request(&["6.6.6.6, 1.2.3.4", "10.0.0.2"], "10.0.0.3:5000");
+
+        assert_eq!(client_ip(&headers, &extensions, 1), ip("10.0.0.2"));
+        assert_eq!(client_ip(&headers, &extensions, 2), ip("1.2.3.4"));
+    }
+
+    #[test]
+    fn falls_back_to_the_peer() {
+        let (headers, extensions) = request(&["1.2.3.4"], "10.0.0.1:5000");
+        assert_eq!(client_ip(&headers, &extensions, 2), ip("10.0.0.1"));
+
+        let (headers, extensions) = request(&["not an ip"], "10.0.0.1:5000");
+        assert_eq!(client_ip(&headers, &extensions, 1), ip("10.0.0.1"));
+    }
+}
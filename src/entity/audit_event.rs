@@ -0,0 +1,112 @@
+use chrono::NaiveDateTime;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, ToSchema)]
+#[sea_orm(table_name = "audit_event")]
+#[schema(as = AuditEvent)]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    /// The user who performed the action, if any.
+    #[sea_orm(indexed)]
+    pub actor_id: Option<i32>,
+    /// The user the action was performed on, if any.
+    #[sea_orm(indexed)]
+    pub subject_id: Option<i32>,
+    pub event_type: EventType,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub client_id: Option<i32>,
+    #[schema(value_type = Object)]
+    pub details: Json,
+    #[sea_orm(created_at)]
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize, ToSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(32))")]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    #[sea_orm(string_value = "login")]
+    Login,
+    #[sea_orm(string_value = "login_failed")]
+    LoginFailed,
+    #[sea_orm(string_value = "login_locked")]
+    LoginLocked,
+    #[sea_orm(string_value = "login_unlocked")]
+    LoginUnlocked,
+    #[sea_orm(string_value = "email_login_requested")]
+    EmailLoginRequested,
+    #[sea_orm(string_value = "email_login")]
+    EmailLogin,
+    #[sea_orm(string_value = "email_login_failed")]
+    EmailLoginFailed,
+    #[sea_orm(string_value = "token_refreshed")]
+    TokenRefreshed,
+    #[sea_orm(string_value = "registration_requested")]
+    RegistrationRequested,
+    #[sea_orm(string_value = "verification_resent")]
+    VerificationResent,
+    #[sea_orm(string_value = "registered")]
+    Registered,
+    #[sea_orm(string_value = "oauth_authorized")]
+    OAuthAuthorized,
+    #[sea_orm(string_value = "oauth_token_exchanged")]
+    OAuthTokenExchanged,
+    #[sea_orm(string_value = "profile_edited")]
+    ProfileEdited,
+    #[sea_orm(string_value = "username_changed")]
+    UsernameChanged,
+    #[sea_orm(string_value = "session_revoked")]
+    SessionRevoked,
+    #[sea_orm(string_value = "all_sessions_revoked")]
+    AllSessionsRevoked,
+    #[sea_orm(string_value = "account_deletion_scheduled")]
+    AccountDeletionScheduled,
+    #[sea_orm(string_value = "account_deletion_cancelled")]
+    AccountDeletionCancelled,
+    #[sea_orm(string_value = "account_deleted")]
+    AccountDeleted,
+    #[sea_orm(string_value = "data_export_requested")]
+    DataExportRequested,
+    #[sea_orm(string_value = "data_export_downloaded")]
+    DataExportDownloaded,
+    #[sea_orm(string_value = "avatar_changed")]
+    AvatarChanged,
+    #[sea_orm(string_value = "avatar_removed")]
+    AvatarRemoved,
+    #[sea_orm(string_value = "admin_user_edited")]
+    AdminUserEdited,
+    #[sea_orm(string_value = "account_suspended")]
+    AccountSuspended,
+    #[sea_orm(string_value = "account_unsuspended")]
+    AccountUnsuspended,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn before_save<C>(self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        if !insert {
+            return Err(DbErr::Custom("audit events are append-only".to_string()));
+        }
+
+        Ok(self)
+    }
+
+    async fn before_delete<C>(self, _db: &C) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        Err(DbErr::Custom("audit events are append-only".to_string()))
+    }
+}
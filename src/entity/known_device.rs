@@ -0,0 +1,24 @@
+use chrono::NaiveDateTime;
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// A device and network a user has signed in from before.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "known_device")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    #[sea_orm(indexed)]
+    pub user_id: i32,
+    /// SHA-256 of the user agent.
+    pub fingerprint: String,
+    /// The /24 (IPv4) or /48 (IPv6) network the sign-in came from.
+    pub ip_range: String,
+    pub first_seen_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,2 +1,146 @@
 pub mod user;
-pub mod oauth_client;
\ No newline at end of file
+pub mod oauth_client;
+pub mod audit_event;
+pub mod known_device;
+pub mod username_redirect;
+pub mod suspension;
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use migration::{Migrator, MigratorTrait};
+    use sea_orm::{ActiveValue, ConnectionTrait, Database, DatabaseConnection, EntityTrait};
+    use serde_json::json;
+
+    use super::{audit_event, suspension, user};
+
+    /// These run against the database at `TEST_DATABASE_URL`, which they wipe, and are
+    /// skipped without one.
+    async fn database() -> Option<DatabaseConnection> {
+        let url = std::env::var("TEST_DATABASE_URL").ok()?;
+        let db = Database::connect(&url).await.unwrap();
+        Migrator::reset(&db).await.unwrap();
+        Some(db)
+    }
+
+    #[tokio::test]
+    async fn adopts_a_hand_made_schema_and_round_trips() {
+        let Some(db) = database().await else {
+            return;
+        };
+
+        // The schema the service ran on before migrations, with an account in it.
+        db.execute_unprepared(
+            r#"CREATE TABLE "user" (
+                "id" serial PRIMARY KEY,
+                "name" varchar NOT NULL,
+                "email" varchar NOT NULL,
+                "avatar" varchar,
+                "salted_password" varchar NOT NULL,
+                "salt" varchar NOT NULL,
+                "created_at" timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                "updated_at" timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+            CREATE UNIQUE INDEX "idx_user_email" ON "user" ("email");
+            INSERT INTO "user" ("name", "email", "salted_password", "salt")
+                VALUES ('old', 'old@example.com', 'hash', 'salt');"#,
+        )
+        .await
+        .unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        let old = user::Entity::find_by_id(1).one(&db).await.unwrap().unwrap();
+        assert_eq!(old.username, "user_1");
+        assert_eq!(old.role, user::Role::User);
+
+        let id = user::Entity::insert(user::ActiveModel {
+            id: ActiveValue::NotSet,
+            name: ActiveValue::Set("new".to_string()),
+            username: ActiveValue::Set("new_user".to_string()),
+            email: ActiveValue::Set("new@example.com".to_string()),
+            avatar: ActiveValue::Set(None),
+            bio: ActiveValue::Set(None),
+            website: ActiveValue::Set(None),
+            locale: ActiveValue::Set(None),
+            timezone: ActiveValue::Set(None),
+            pronouns: ActiveValue::Set(None),
+            birthday: ActiveValue::Set(None),
+            profile_visibility: ActiveValue::Set(json!({})),
+            role: ActiveValue::Set(user::Role::Moderator),
+            salted_password: ActiveValue::Set("hash".to_string()),
+            salt: ActiveValue::Set("salt".to_string()),
+            deletion_scheduled_at: ActiveValue::Set(None),
+            created_at: ActiveValue::NotSet,
+            updated_at: ActiveValue::NotSet,
+        })
+        .exec(&db)
+        .await
+        .unwrap()
+        .last_insert_id;
+        let new = user::Entity::find_by_id(id)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((new.id, new.username.as_str()), (2, "new_user"));
+        assert_eq!(new.role, user::Role::Moderator);
+
+        let now = Utc::now().naive_utc();
+        let event = audit_event::Entity::insert(audit_event::ActiveModel {
+            id: ActiveValue::NotSet,
+            actor_id: ActiveValue::Set(Some(new.id)),
+            subject_id: ActiveValue::Set(Some(old.id)),
+            event_type: ActiveValue::Set(audit_event::EventType::Login),
+            ip: ActiveValue::Set(None),
+            user_agent: ActiveValue::Set(None),
+            client_id: ActiveValue::Set(Some(7)),
+            details: ActiveValue::Set(json!({})),
+            created_at: ActiveValue::NotSet,
+        })
+        .exec(&db)
+        .await
+        .unwrap()
+        .last_insert_id;
+        let event = audit_event::Entity::find_by_id(event)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (event.actor_id, event.subject_id, event.client_id),
+            (Some(2), Some(1), Some(7))
+        );
+
+        let suspension = suspension::Entity::insert(suspension::ActiveModel {
+            id: ActiveValue::NotSet,
+            user_id: ActiveValue::Set(old.id),
+            reason: ActiveValue::Set("spam".to_string()),
+            note: ActiveValue::Set(None),
+            moderator_id: ActiveValue::Set(Some(new.id)),
+            starts_at: ActiveValue::Set(now),
+            ends_at: ActiveValue::Set(None),
+            lifted_at: ActiveValue::Set(Some(now)),
+            lifted_by: ActiveValue::Set(Some(new.id)),
+        })
+        .exec(&db)
+        .await
+        .unwrap()
+        .last_insert_id;
+        let suspension = suspension::Entity::find_by_id(suspension)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (
+                suspension.user_id,
+                suspension.moderator_id,
+                suspension.lifted_by
+            ),
+            (1, Some(2), Some(2))
+        );
+
+        Migrator::down(&db, None).await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+    }
+}
@@ -5,7 +5,7 @@ use sea_orm::entity::prelude::*;
 #[sea_orm(table_name = "oauth_client")]
 pub struct Model {
     #[sea_orm(primary_key)]
-    pub client_id: u32,
+    pub client_id: i32,
     pub official: bool,
     pub client_secret: String,
     #[sea_orm(created_at)]
@@ -17,4 +17,4 @@ pub struct Model {
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {}
 
-impl ActiveModelBehavior for ActiveModel {}
\ No newline at end of file
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,41 @@
+use chrono::NaiveDateTime;
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A period during which a user may not use their account.
+///
+/// Suspensions are kept after they end. Lifting one sets `lifted_at`, to the end of the
+/// suspension with no `lifted_by` if it simply expired.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, ToSchema)]
+#[sea_orm(table_name = "suspension")]
+#[schema(as = Suspension)]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    #[sea_orm(indexed)]
+    pub user_id: i32,
+    /// Shown to the suspended user.
+    pub reason: String,
+    /// Only visible to moderators.
+    pub note: Option<String>,
+    pub moderator_id: Option<i32>,
+    pub starts_at: NaiveDateTime,
+    /// Absent for permanent bans.
+    pub ends_at: Option<NaiveDateTime>,
+    pub lifted_at: Option<NaiveDateTime>,
+    pub lifted_by: Option<i32>,
+}
+
+impl Model {
+    pub fn is_active(&self, now: NaiveDateTime) -> bool {
+        self.lifted_at.is_none()
+            && self.starts_at <= now
+            && self.ends_at.is_none_or(|ends_at| ends_at > now)
+    }
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,24 +1,69 @@
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
 use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
 #[sea_orm(table_name = "user")]
 pub struct Model {
     #[sea_orm(primary_key)]
-    pub id: u32,
+    pub id: i32,
     pub name: String,
-    #[sea_orm(indexed)]
+    /// Unique handle, always stored in lowercase.
+    #[sea_orm(unique)]
+    pub username: String,
+    #[sea_orm(unique)]
     pub email: String,
     pub avatar: Option<String>,
+    pub bio: Option<String>,
+    pub website: Option<String>,
+    /// BCP 47 language tag, e.g. `zh-CN`.
+    pub locale: Option<String>,
+    /// IANA time zone name, e.g. `Asia/Shanghai`.
+    pub timezone: Option<String>,
+    pub pronouns: Option<String>,
+    pub birthday: Option<NaiveDate>,
+    /// Who may see each of the fields above, see [`crate::data::profile::FieldVisibility`].
+    pub profile_visibility: Json,
+    pub role: Role,
     pub salted_password: String,
     pub salt: String,
+    /// When the account is going to be deleted, unless the user logs in before then.
+    pub deletion_scheduled_at: Option<NaiveDateTime>,
     #[sea_orm(created_at)]
     pub created_at: NaiveDateTime,
     #[sea_orm(updated_at)]
     pub updated_at: NaiveDateTime,
 }
 
+/// What a user may do beyond managing their own account, see
+/// [`crate::data::credential::Permission`].
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    EnumIter,
+    DeriveActiveEnum,
+    Serialize,
+    Deserialize,
+    ToSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(16))")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    #[sea_orm(string_value = "user")]
+    User,
+    #[sea_orm(string_value = "moderator")]
+    Moderator,
+    #[sea_orm(string_value = "admin")]
+    Admin,
+}
+
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {}
 
-impl ActiveModelBehavior for ActiveModel {}
\ No newline at end of file
+impl ActiveModelBehavior for ActiveModel {}
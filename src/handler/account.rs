@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::{ActiveModelTrait, EntityTrait, Set};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+
+use crate::{
+    data::{credential::Claims, error::Error, session::ClientInfo},
+    entity::{audit_event::EventType, user},
+    handler::{email_login::consume_login_code, openapi::errors},
+    utils::{audit::AuditEvent, db::StanderizeError, encryption::salt_password},
+    AppState,
+};
+
+/// Proof that the user is present, either their password or a login code that was
+/// requested through `/login/email`.
+#[derive(Deserialize, ToSchema, Debug)]
+#[serde(untagged)]
+pub enum Reauthentication {
+    Password { hashed_password: String },
+    Code { code: String },
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ScheduledDeletion {
+    deletion_scheduled_at: NaiveDateTime,
+}
+
+errors!(DeleteMeErrors:
+    InvalidToken,
+    ExpiredToken,
+    AccountSuspended,
+    Forbidden,
+    NotFound,
+    IncorrectEmailOrPassword,
+    IncorrectCode,
+    InternalServerError,
+);
+
+/// Schedules the deletion of the current account and signs it out everywhere.
+///
+/// Logging in again before the grace period ends cancels the deletion.
+#[utoipa::path(
+    delete,
+    path = "/v0/me",
+    tag = "account",
+    request_body = Reauthentication,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "When the account will be deleted", body = ScheduledDeletion),
+        DeleteMeErrors,
+    ),
+)]
+pub async fn delete_me(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    claims: Claims<0>,
+    Json(reauth): Json<Reauthentication>,
+) -> Result<Json<ScheduledDeletion>, Error> {
+    if claims.scopes.is_some() {
+        return Err(Error::Forbidden);
+    }
+
+    let user = user::Entity::find_by_id(claims.uid)
+        .one(&state.db)
+        .await
+        .warn_err()?
+        .ok_or(Error::NotFound)?;
+
+    match reauth {
+        Reauthentication::Password { hashed_password } => {
+            if user.salted_password != salt_password(&hashed_password, &user.salt) {
+                return Err(Error::IncorrectEmailOrPassword);
+            }
+        }
+        Reauthentication::Code { code } => {
+            if consume_login_code(&state, &client, &user.email, &code).await? != user.id {
+                return Err(Error::IncorrectCode);
+            }
+        }
+    }
+
+    let deletion_scheduled_at = Utc::now().naive_utc()
+        + chrono::Duration::days(state.config.accounts.deletion_grace_days);
+    let mut active: user::ActiveModel = user.into();
+    active.deletion_scheduled_at = Set(Some(deletion_scheduled_at));
+    active.update(&state.db).await.warn_err()?;
+
+    state.store.revoke_all_sessions(claims.uid).await?;
+
+    AuditEvent::new(EventType::AccountDeletionScheduled)
+        .user(claims.uid)
+        .client(&client)
+        .details(json!({ "deletion_scheduled_at": deletion_scheduled_at }))
+        .record(&state.db)
+        .await;
+
+    Ok(Json(ScheduledDeletion {
+        deletion_scheduled_at,
+    }))
+}
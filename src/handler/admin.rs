@@ -0,0 +1,539 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, Condition, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect, Set, SqlErr, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::{
+    data::{
+        credential::{Admin, Permission},
+        error::Error,
+        session::ClientInfo,
+    },
+    entity::{
+        audit_event::EventType,
+        suspension,
+        user::{self, Role},
+    },
+    handler::{openapi::errors, profile::MyProfile, register::complete_registration},
+    utils::{
+        audit::AuditEvent,
+        db::StanderizeError,
+        suspension::{self as suspensions, active_suspension},
+        username::{availability, normalize, rename, validate_username, Availability},
+    },
+    AppState,
+};
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct UserSearch {
+    /// Matched against email, handle and display name.
+    q: Option<String>,
+    role: Option<Role>,
+    cursor: Option<i32>,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct AdminUser {
+    #[serde(flatten)]
+    profile: MyProfile,
+    role: Role,
+    suspension: Option<suspension::Model>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct UserPage {
+    users: Vec<AdminUser>,
+    /// Pass as `cursor` to fetch the next page; absent on the last page.
+    next_cursor: Option<i32>,
+}
+
+#[derive(Deserialize, ToSchema, Debug, Validate)]
+pub struct AdminUserEdit {
+    #[validate(length(min = 3, max = 25))]
+    name: Option<String>,
+    #[validate(custom(function = "validate_username"))]
+    username: Option<String>,
+    #[validate(email)]
+    email: Option<String>,
+    role: Option<Role>,
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct ForceVerify {
+    email: String,
+}
+
+#[derive(Deserialize, ToSchema, Debug, Validate)]
+pub struct SuspendBody {
+    /// Shown to the user.
+    #[validate(length(min = 1, max = 500))]
+    reason: String,
+    #[validate(length(max = 2000))]
+    note: Option<String>,
+    /// Absent for a permanent ban.
+    until: Option<NaiveDateTime>,
+}
+
+async fn admin_user(state: &AppState, user: user::Model) -> Result<AdminUser, Error> {
+    Ok(AdminUser {
+        role: user.role,
+        suspension: active_suspension(&state.db, user.id).await?,
+        profile: user.into(),
+    })
+}
+
+async fn find_user(state: &AppState, id: i32) -> Result<user::Model, Error> {
+    user::Entity::find_by_id(id)
+        .one(&state.db)
+        .await
+        .warn_err()?
+        .ok_or(Error::NotFound)
+}
+
+errors!(UsersErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    Forbidden,
+    InternalServerError,
+);
+
+/// Searches users, newest first.
+#[utoipa::path(
+    get,
+    path = "/v0/admin/users",
+    tag = "admin",
+    params(UserSearch),
+    security(("bearer" = []), ("oauth2" = ["admin"])),
+    responses(
+        (status = 200, description = "A page of users", body = UserPage),
+        UsersErrors,
+    ),
+)]
+pub async fn users(
+    state: State<Arc<AppState>>,
+    admin: Admin,
+    Query(query): Query<UserSearch>,
+) -> Result<Json<UserPage>, Error> {
+    admin.require(Permission::ViewUsers)?;
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let mut select = user::Entity::find();
+
+    if let Some(q) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        select = select.filter(
+            Condition::any()
+                .add(user::Column::Email.contains(q.to_lowercase()))
+                .add(user::Column::Username.contains(normalize(q)))
+                .add(user::Column::Name.contains(q)),
+        );
+    }
+    if let Some(role) = query.role {
+        select = select.filter(user::Column::Role.eq(role));
+    }
+    if let Some(cursor) = query.cursor {
+        select = select.filter(user::Column::Id.lt(cursor));
+    }
+
+    let mut found = select
+        .order_by_desc(user::Column::Id)
+        .limit(limit + 1)
+        .all(&state.db)
+        .await
+        .warn_err()?;
+
+    let next_cursor = if found.len() as u64 > limit {
+        found.truncate(limit as usize);
+        found.last().map(|user| user.id)
+    } else {
+        None
+    };
+
+    let mut users = Vec::with_capacity(found.len());
+    for user in found {
+        users.push(admin_user(&state, user).await?);
+    }
+
+    Ok(Json(UserPage { users, next_cursor }))
+}
+
+errors!(GetUserErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    Forbidden,
+    NotFound,
+    InternalServerError,
+);
+
+/// A user, with their role and the suspension in effect.
+#[utoipa::path(
+    get,
+    path = "/v0/admin/users/{id}",
+    tag = "admin",
+    params(("id" = i32, Path, description = "The id of the user")),
+    security(("bearer" = []), ("oauth2" = ["admin"])),
+    responses(
+        (status = 200, description = "The user", body = AdminUser),
+        GetUserErrors,
+    ),
+)]
+pub async fn get_user(
+    state: State<Arc<AppState>>,
+    admin: Admin,
+    Path(id): Path<i32>,
+) -> Result<Json<AdminUser>, Error> {
+    admin.require(Permission::ViewUsers)?;
+
+    let user = find_user(&state, id).await?;
+
+    Ok(Json(admin_user(&state, user).await?))
+}
+
+errors!(EditUserErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    Forbidden,
+    InvalidFields,
+    NotFound,
+    RegisteredEmail,
+    UsernameTaken,
+    InternalServerError,
+);
+
+/// Changes a user's name, handle, email or role. Admins cannot change their own role.
+#[utoipa::path(
+    patch,
+    path = "/v0/admin/users/{id}",
+    tag = "admin",
+    params(("id" = i32, Path, description = "The id of the user")),
+    request_body = AdminUserEdit,
+    security(("bearer" = []), ("oauth2" = ["admin"])),
+    responses(
+        (status = 200, description = "The edited user", body = AdminUser),
+        EditUserErrors,
+    ),
+)]
+pub async fn edit_user(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    admin: Admin,
+    Path(id): Path<i32>,
+    Json(params): Json<AdminUserEdit>,
+) -> Result<Json<AdminUser>, Error> {
+    admin.require(Permission::EditUsers)?;
+    params.validate()?;
+
+    let user = find_user(&state, id).await?;
+    let mut active: user::ActiveModel = user.clone().into();
+    let mut changed = serde_json::Map::new();
+    let txn = state.db.begin().await.warn_err()?;
+
+    if let Some(name) = params.name {
+        changed.insert("name".into(), json!({ "from": user.name, "to": name }));
+        active.name = Set(name);
+    }
+    if let Some(username) = params
+        .username
+        .as_deref()
+        .map(normalize)
+        .filter(|username| *username != user.username)
+    {
+        let store = state.store.as_ref();
+        if availability(&state.db, store, &username, Some(id), None).await?
+            != Availability::Available
+        {
+            return Err(Error::UsernameTaken);
+        }
+        rename(
+            &txn,
+            &user,
+            &username,
+            state.config.accounts.username_redirect_days,
+        )
+        .await?;
+        changed.insert(
+            "username".into(),
+            json!({ "from": user.username, "to": username }),
+        );
+    }
+    if let Some(email) = params.email.map(|email| email.trim().to_lowercase()) {
+        changed.insert("email".into(), json!({ "from": user.email, "to": email }));
+        active.email = Set(email);
+    }
+    if let Some(role) = params.role {
+        admin.require(Permission::ManageRoles)?;
+        if id == admin.claims.uid {
+            return Err(Error::Forbidden);
+        }
+        changed.insert("role".into(), json!({ "from": user.role, "to": role }));
+        active.role = Set(role);
+    }
+
+    let user = active.update(&txn).await.or_else(|e| match e.sql_err() {
+        Some(SqlErr::UniqueConstraintViolation(message)) if message.contains("email") => {
+            Err(Error::RegisteredEmail)
+        }
+        Some(SqlErr::UniqueConstraintViolation(_)) => Err(Error::UsernameTaken),
+        _ => Err(e).warn_err(),
+    })?;
+    txn.commit().await.warn_err()?;
+
+    AuditEvent::new(EventType::AdminUserEdited)
+        .actor(admin.claims.uid)
+        .subject(id)
+        .client(&client)
+        .details(json!({ "fields": changed }))
+        .record(&state.db)
+        .await;
+
+    Ok(Json(admin_user(&state, user).await?))
+}
+
+errors!(ForceVerifyErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    Forbidden,
+    NotFound,
+    RegisteredEmail,
+    UsernameTaken,
+    InternalServerError,
+);
+
+/// Completes a pending registration as if its verification link had been followed.
+#[utoipa::path(
+    post,
+    path = "/v0/admin/registrations/verify",
+    tag = "admin",
+    request_body = ForceVerify,
+    security(("bearer" = []), ("oauth2" = ["admin"])),
+    responses(
+        (status = 200, description = "The created user", body = AdminUser),
+        ForceVerifyErrors,
+    ),
+)]
+pub async fn force_verify(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    admin: Admin,
+    Json(params): Json<ForceVerify>,
+) -> Result<Json<AdminUser>, Error> {
+    admin.require(Permission::VerifyEmails)?;
+
+    let token = state
+        .store
+        .pending_verification(&params.email)
+        .await?
+        .ok_or(Error::NotFound)?;
+    let uid = complete_registration(&state, &token).await?;
+
+    AuditEvent::new(EventType::Registered)
+        .actor(admin.claims.uid)
+        .subject(uid)
+        .client(&client)
+        .details(json!({ "forced": true }))
+        .record(&state.db)
+        .await;
+
+    let user = find_user(&state, uid).await?;
+
+    Ok(Json(admin_user(&state, user).await?))
+}
+
+errors!(RevokeUserSessionsErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    Forbidden,
+    NotFound,
+    InternalServerError,
+);
+
+/// Signs a user out everywhere.
+#[utoipa::path(
+    delete,
+    path = "/v0/admin/users/{id}/sessions",
+    tag = "admin",
+    params(("id" = i32, Path, description = "The id of the user")),
+    security(("bearer" = []), ("oauth2" = ["admin"])),
+    responses(
+        (status = 200, description = "Every session of the user is revoked"),
+        RevokeUserSessionsErrors,
+    ),
+)]
+pub async fn revoke_user_sessions(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    admin: Admin,
+    Path(id): Path<i32>,
+) -> Result<(), Error> {
+    admin.require(Permission::RevokeSessions)?;
+
+    find_user(&state, id).await?;
+    state.store.revoke_all_sessions(id).await?;
+
+    AuditEvent::new(EventType::AllSessionsRevoked)
+        .actor(admin.claims.uid)
+        .subject(id)
+        .client(&client)
+        .record(&state.db)
+        .await;
+
+    Ok(())
+}
+
+errors!(SuspendErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    Forbidden,
+    InvalidFields,
+    BadRequest,
+    NotFound,
+    InternalServerError,
+);
+
+/// Suspends a user until `until`, or for good, and signs them out everywhere.
+#[utoipa::path(
+    put,
+    path = "/v0/admin/users/{id}/suspension",
+    tag = "admin",
+    params(("id" = i32, Path, description = "The id of the user")),
+    request_body = SuspendBody,
+    security(("bearer" = []), ("oauth2" = ["admin"])),
+    responses(
+        (status = 200, description = "The suspension", body = suspension::Model),
+        SuspendErrors,
+    ),
+)]
+pub async fn suspend(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    admin: Admin,
+    Path(id): Path<i32>,
+    Json(params): Json<SuspendBody>,
+) -> Result<Json<suspension::Model>, Error> {
+    admin.require(Permission::SuspendUsers)?;
+    params.validate()?;
+
+    let now = Utc::now().naive_utc();
+    if params.until.is_some_and(|until| until <= now) || id == admin.claims.uid {
+        return Err(Error::BadRequest);
+    }
+
+    let target = find_user(&state, id).await?;
+    if target.role != Role::User {
+        admin.require(Permission::ManageRoles)?;
+    }
+
+    let suspension = suspension::ActiveModel {
+        id: ActiveValue::NotSet,
+        user_id: Set(id),
+        reason: Set(params.reason),
+        note: Set(params.note),
+        moderator_id: Set(Some(admin.claims.uid)),
+        starts_at: Set(now),
+        ends_at: Set(params.until),
+        lifted_at: Set(None),
+        lifted_by: Set(None),
+    }
+    .insert(&state.db)
+    .await
+    .warn_err()?;
+
+    suspensions::cache(state.store.as_ref(), &suspension).await?;
+    state.store.revoke_all_sessions(id).await?;
+
+    AuditEvent::new(EventType::AccountSuspended)
+        .actor(admin.claims.uid)
+        .subject(id)
+        .client(&client)
+        .details(json!({
+            "suspension_id": suspension.id,
+            "reason": suspension.reason,
+            "until": suspension.ends_at,
+        }))
+        .record(&state.db)
+        .await;
+
+    Ok(Json(suspension))
+}
+
+errors!(UnsuspendErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    Forbidden,
+    NotFound,
+    InternalServerError,
+);
+
+/// Lifts every suspension currently in effect for a user.
+#[utoipa::path(
+    delete,
+    path = "/v0/admin/users/{id}/suspension",
+    tag = "admin",
+    params(("id" = i32, Path, description = "The id of the user")),
+    security(("bearer" = []), ("oauth2" = ["admin"])),
+    responses(
+        (status = 200, description = "The user is no longer suspended"),
+        UnsuspendErrors,
+    ),
+)]
+pub async fn unsuspend(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    admin: Admin,
+    Path(id): Path<i32>,
+) -> Result<(), Error> {
+    admin.require(Permission::SuspendUsers)?;
+
+    let now = Utc::now().naive_utc();
+    let lifted = suspension::Entity::update_many()
+        .col_expr(suspension::Column::LiftedAt, now.into())
+        .col_expr(suspension::Column::LiftedBy, admin.claims.uid.into())
+        .filter(suspension::Column::UserId.eq(id))
+        .filter(suspension::Column::LiftedAt.is_null())
+        .exec(&state.db)
+        .await
+        .warn_err()?;
+    if lifted.rows_affected == 0 {
+        return Err(Error::NotFound);
+    }
+
+    suspensions::clear_cache(state.store.as_ref(), id).await?;
+
+    AuditEvent::new(EventType::AccountUnsuspended)
+        .actor(admin.claims.uid)
+        .subject(id)
+        .client(&client)
+        .record(&state.db)
+        .await;
+
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use chrono::NaiveDateTime;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Select};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    data::{
+        credential::{scopes, Admin, Claims, Permission, Scope},
+        error::Error,
+    },
+    entity::audit_event::{self, EventType},
+    handler::openapi::errors,
+    utils::db::StanderizeError,
+    AppState,
+};
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct ActivityQuery {
+    cursor: Option<i64>,
+    limit: Option<u64>,
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    actor_id: Option<i32>,
+    subject_id: Option<i32>,
+    event_type: Option<EventType>,
+    client_id: Option<i32>,
+    ip: Option<String>,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    cursor: Option<i64>,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct AuditPage {
+    events: Vec<audit_event::Model>,
+    /// Pass as `cursor` to fetch the next (older) page; absent on the last page.
+    next_cursor: Option<i64>,
+}
+
+errors!(ActivityErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    InternalServerError,
+);
+
+/// Security events concerning the current user, newest first.
+#[utoipa::path(
+    get,
+    path = "/v0/me/activity",
+    tag = "account",
+    params(ActivityQuery),
+    security(("bearer" = []), ("oauth2" = ["activity.read"])),
+    responses(
+        (status = 200, description = "A page of events", body = AuditPage),
+        ActivityErrors,
+    ),
+)]
+pub async fn activity(
+    state: State<Arc<AppState>>,
+    claims: Claims<{ scopes(&[Scope::ActivityRead]) }>,
+    Query(query): Query<ActivityQuery>,
+) -> Result<Json<AuditPage>, Error> {
+    let select = audit_event::Entity::find().filter(audit_event::Column::SubjectId.eq(claims.uid));
+
+    Ok(Json(page(&state, select, query.cursor, query.limit).await?))
+}
+
+errors!(EventsErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    Forbidden,
+    InternalServerError,
+);
+
+/// Searches the whole audit log, newest first.
+#[utoipa::path(
+    get,
+    path = "/v0/admin/audit",
+    tag = "admin",
+    params(AuditQuery),
+    security(("bearer" = []), ("oauth2" = ["admin"])),
+    responses(
+        (status = 200, description = "A page of events", body = AuditPage),
+        EventsErrors,
+    ),
+)]
+pub async fn events(
+    state: State<Arc<AppState>>,
+    admin: Admin,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<AuditPage>, Error> {
+    admin.require(Permission::ViewAudit)?;
+
+    let mut select = audit_event::Entity::find();
+
+    if let Some(actor_id) = query.actor_id {
+        select = select.filter(audit_event::Column::ActorId.eq(actor_id));
+    }
+    if let Some(subject_id) = query.subject_id {
+        select = select.filter(audit_event::Column::SubjectId.eq(subject_id));
+    }
+    if let Some(event_type) = query.event_type {
+        select = select.filter(audit_event::Column::EventType.eq(event_type));
+    }
+    if let Some(client_id) = query.client_id {
+        select = select.filter(audit_event::Column::ClientId.eq(client_id));
+    }
+    if let Some(ip) = query.ip {
+        select = select.filter(audit_event::Column::Ip.eq(ip));
+    }
+    if let Some(since) = query.since {
+        select = select.filter(audit_event::Column::CreatedAt.gte(since));
+    }
+    if let Some(until) = query.until {
+        select = select.filter(audit_event::Column::CreatedAt.lt(until));
+    }
+
+    Ok(Json(page(&state, select, query.cursor, query.limit).await?))
+}
+
+async fn page(
+    state: &AppState,
+    mut select: Select<audit_event::Entity>,
+    cursor: Option<i64>,
+    limit: Option<u64>,
+) -> Result<AuditPage, Error> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    if let Some(cursor) = cursor {
+        select = select.filter(audit_event::Column::Id.lt(cursor));
+    }
+
+    let mut events = select
+        .order_by_desc(audit_event::Column::Id)
+        .limit(limit + 1)
+        .all(&state.db)
+        .await
+        .warn_err()?;
+
+    let next_cursor = if events.len() as u64 > limit {
+        events.truncate(limit as usize);
+        events.last().map(|event| event.id)
+    } else {
+        None
+    };
+
+    Ok(AuditPage {
+        events,
+        next_cursor,
+    })
+}
@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Multipart, State},
+    Json,
+};
+use rand::distributions::{Alphanumeric, DistString};
+use sea_orm::{ActiveModelTrait, EntityTrait, Set};
+
+use crate::{
+    data::{
+        credential::{scopes, Claims, Scope},
+        error::Error,
+        session::ClientInfo,
+    },
+    entity::{audit_event::EventType, user},
+    handler::openapi::errors,
+    utils::{
+        audit::AuditEvent,
+        avatar::{process, Avatar},
+        db::StanderizeError,
+    },
+    AppState,
+};
+
+errors!(UploadAvatarErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    BadRequest,
+    UnsupportedImage,
+    NotFound,
+    RateLimited,
+    InternalServerError,
+);
+
+/// Replaces the current user's avatar with the image in the `avatar` field of a
+/// multipart upload.
+#[utoipa::path(
+    put,
+    path = "/v0/me/avatar",
+    tag = "profile",
+    request_body(
+        content_type = "multipart/form-data",
+        description = "A PNG, JPEG, WebP or GIF image in the `avatar` field",
+    ),
+    security(("bearer" = []), ("oauth2" = ["profile.write"])),
+    responses(
+        (status = 200, description = "The new avatar", body = Avatar),
+        (status = 413, description = "The image is larger than `avatars.max_bytes`"),
+        UploadAvatarErrors,
+    ),
+)]
+pub async fn upload_avatar(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    claims: Claims<{ scopes(&[Scope::ProfileWrite]) }>,
+    mut multipart: Multipart,
+) -> Result<Json<Avatar>, Error> {
+    let mut data = None;
+    while let Some(field) = multipart.next_field().await.debug_err()? {
+        if field.name() == Some("avatar") {
+            data = Some(field.bytes().await.map_err(|_| Error::BadRequest)?);
+            break;
+        }
+    }
+    let data = data.ok_or(Error::BadRequest)?;
+
+    let renditions = tokio::task::spawn_blocking(move || process(&data))
+        .await
+        .warn_err()??;
+
+    let user = user::Entity::find_by_id(claims.uid)
+        .one(&state.db)
+        .await
+        .warn_err()?
+        .ok_or(Error::NotFound)?;
+
+    // Every upload gets a fresh prefix so that caches never serve a stale image.
+    let version = Alphanumeric.sample_string(&mut rand::thread_rng(), 12);
+    let prefix = format!("avatars/{}/{}", claims.uid, version);
+    for rendition in renditions {
+        state
+            .storage
+            .put(
+                &format!("{}/{}", prefix, rendition.name),
+                rendition.content_type,
+                rendition.data,
+            )
+            .await?;
+    }
+
+    let base = state.storage.url(&prefix);
+    let previous = user.avatar.clone();
+    let email = user.email.clone();
+    let mut user: user::ActiveModel = user.into();
+    user.avatar = Set(Some(base.clone()));
+    user.update(&state.db).await.warn_err()?;
+
+    if let Some(previous) = previous {
+        remove_version(&state, claims.uid, &previous).await;
+    }
+
+    AuditEvent::new(EventType::AvatarChanged)
+        .user(claims.uid)
+        .client(&client)
+        .record(&state.db)
+        .await;
+
+    Ok(Json(Avatar::new(Some(&base), &email)))
+}
+
+errors!(DeleteAvatarErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    NotFound,
+    RateLimited,
+    InternalServerError,
+);
+
+/// Removes the current user's avatar, going back to the fallback.
+#[utoipa::path(
+    delete,
+    path = "/v0/me/avatar",
+    tag = "profile",
+    security(("bearer" = []), ("oauth2" = ["profile.write"])),
+    responses(
+        (status = 200, description = "The fallback avatar", body = Avatar),
+        DeleteAvatarErrors,
+    ),
+)]
+pub async fn delete_avatar(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    claims: Claims<{ scopes(&[Scope::ProfileWrite]) }>,
+) -> Result<Json<Avatar>, Error> {
+    let user = user::Entity::find_by_id(claims.uid)
+        .one(&state.db)
+        .await
+        .warn_err()?
+        .ok_or(Error::NotFound)?;
+
+    let Some(previous) = user.avatar.clone() else {
+        return Ok(Json(Avatar::new(None, &user.email)));
+    };
+    let email = user.email.clone();
+    let mut user: user::ActiveModel = user.into();
+    user.avatar = Set(None);
+    user.update(&state.db).await.warn_err()?;
+
+    remove_version(&state, claims.uid, &previous).await;
+
+    AuditEvent::new(EventType::AvatarRemoved)
+        .user(claims.uid)
+        .client(&client)
+        .record(&state.db)
+        .await;
+
+    Ok(Json(Avatar::new(None, &email)))
+}
+
+/// Deletes the files of a replaced avatar. `base` is the URL stored in `user.avatar`,
+/// which ends with the version it was uploaded under.
+async fn remove_version(state: &AppState, uid: i32, base: &str) {
+    let Some(version) = base.rsplit('/').next() else {
+        return;
+    };
+    if state
+        .storage
+        .delete_prefix(&format!("avatars/{}/{}/", uid, version))
+        .await
+        .is_err()
+    {
+        tracing::warn!("failed to delete old avatar of user {}", uid);
+    }
+}
@@ -0,0 +1,350 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use askama::Template;
+use axum::extract::State;
+use axum::Json;
+use lettre::message::{header, SinglePart};
+use lettre::Message;
+use rand::Rng;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::data::error::Error;
+use crate::data::session::ClientInfo;
+use crate::entity::audit_event::EventType;
+use crate::entity::user;
+use crate::handler::login::Token;
+use crate::handler::openapi::errors;
+use crate::store::{generate_refresh_token, CodeCheck, TokenPurpose};
+use crate::utils::account::cancel_deletion;
+use crate::utils::audit::AuditEvent;
+use crate::utils::captcha::{verify_captcha, Captcha};
+use crate::utils::db::StanderizeError;
+use crate::utils::device::check_new_device;
+use crate::utils::lockout::{check_lockout, LoginSubject};
+use crate::utils::metrics::{LOGINS, TOKENS_ISSUED};
+use crate::utils::suspension;
+use crate::AppState;
+
+#[derive(Template)]
+#[template(path = "email_login.html")]
+struct EmailLoginTemplate<'a> {
+    login_link: Option<&'a str>,
+    code: Option<&'a str>,
+    valid_minutes: u64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, Copy)]
+pub enum EmailLoginMethod {
+    #[serde(rename = "link")]
+    Link,
+    #[serde(rename = "code")]
+    Code,
+}
+
+impl EmailLoginMethod {
+    /// The `method` label of the logins metric.
+    fn metric_label(self) -> &'static str {
+        match self {
+            EmailLoginMethod::Link => "email_link",
+            EmailLoginMethod::Code => "email_code",
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema, Debug, Validate)]
+pub struct EmailLoginBody {
+    #[validate(email)]
+    email: String,
+    method: EmailLoginMethod,
+    captcha: Captcha,
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+#[serde(untagged)]
+pub enum EmailLoginVerifyBody {
+    Link { token: String },
+    Code { email: String, code: String },
+}
+
+errors!(EmailLoginErrors:
+    InvalidFields,
+    MissingCaptchaToken,
+    InvalidCaptcha,
+    TimeOutOrDuplicateCaptcha,
+    BadRequest,
+    RateLimited,
+    InternalServerError,
+);
+
+/// Sends a one-click login link or a 6-digit login code to the given email.
+///
+/// Unknown emails are answered the same way as known ones so that this
+/// endpoint cannot be used to probe for registered accounts.
+#[utoipa::path(
+    post,
+    path = "/v0/login/email",
+    tag = "auth",
+    request_body = EmailLoginBody,
+    responses(
+        (status = 200, description = "The email is sent, if the account exists"),
+        EmailLoginErrors,
+    ),
+)]
+pub async fn login_email(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    Json(data): Json<EmailLoginBody>,
+) -> Result<(), Error> {
+    data.validate()?;
+
+    verify_captcha(&state.config.captcha, data.captcha, client.ip.as_deref()).await?;
+
+    let Some(user) = user::Entity::find()
+        .filter(user::Column::Email.eq(&data.email))
+        .one(&state.db)
+        .await
+        .warn_err()?
+    else {
+        tracing::debug!("email login requested for unknown email: {}", data.email);
+        return Ok(());
+    };
+
+    let tokens = &state.config.tokens;
+    let (link, code, ttl) = match data.method {
+        EmailLoginMethod::Link => {
+            let ttl = Duration::from_secs(tokens.email_login_link_ttl_seconds);
+            let token = generate_refresh_token();
+            state
+                .store
+                .store_token(TokenPurpose::EmailLogin, &token, &user.id.to_string(), ttl)
+                .await?;
+
+            let link = state
+                .config
+                .server
+                .link(&format!("/login/email?token={}", token));
+            (Some(link), None, ttl)
+        }
+        EmailLoginMethod::Code => {
+            let ttl = Duration::from_secs(tokens.email_login_code_ttl_seconds);
+            let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+            state
+                .store
+                .store_login_code(&data.email, &code, user.id, ttl)
+                .await?;
+
+            (None, Some(code), ttl)
+        }
+    };
+
+    let email = Message::builder()
+        .subject("登录WebSxz")
+        .from(state.mailer.from.clone())
+        .to(data.email.parse().map_err(|e| {
+            tracing::debug!("email illegal: {}", e);
+            Error::BadRequest
+        })?)
+        .singlepart(
+            SinglePart::builder()
+                .header(header::ContentType::TEXT_HTML)
+                .body(
+                    EmailLoginTemplate {
+                        login_link: link.as_deref(),
+                        code: code.as_deref(),
+                        valid_minutes: ttl.as_secs() / 60,
+                    }
+                    .render()
+                    .map_err(|e| {
+                        tracing::warn!("email template render failed: {}", e);
+                        Error::InternalServerError
+                    })?,
+                ),
+        )
+        .map_err(|e| {
+            tracing::warn!("failed to build email: {}", e);
+            Error::InternalServerError
+        })?;
+
+    state.mailer.send(email).await?;
+
+    AuditEvent::new(EventType::EmailLoginRequested)
+        .subject(user.id)
+        .client(&client)
+        .details(json!({ "method": data.method }))
+        .record(&state.db)
+        .await;
+
+    Ok(())
+}
+
+errors!(VerifyEmailLoginErrors:
+    NotFound,
+    IncorrectCode,
+    TooManyAttempts,
+    RateLimited,
+    AccountSuspended,
+    InternalServerError,
+);
+
+/// Exchanges a login link token or a login code for a token pair.
+///
+/// Accounts and IPs locked out after failed logins are refused, the same as for password
+/// logins.
+#[utoipa::path(
+    post,
+    path = "/v0/login/email/verify",
+    tag = "auth",
+    request_body = EmailLoginVerifyBody,
+    responses(
+        (status = 200, description = "A new session", body = Token),
+        VerifyEmailLoginErrors,
+    ),
+)]
+pub async fn verify_login_email(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    Json(data): Json<EmailLoginVerifyBody>,
+) -> Result<Json<Token>, Error> {
+    let store = state.store.as_ref();
+    let ip = client.ip.as_deref().map(LoginSubject::Ip);
+
+    let (uid, method) = match data {
+        EmailLoginVerifyBody::Link { token } => {
+            let method = EmailLoginMethod::Link;
+            check_locked(&state, &client, method, None, ip.as_slice()).await?;
+            let uid = store
+                .consume_token(TokenPurpose::EmailLogin, &token)
+                .await?
+                .and_then(|uid| uid.parse::<i32>().ok());
+
+            (uid.ok_or(Error::NotFound), method)
+        }
+        EmailLoginVerifyBody::Code { email, code } => {
+            let method = EmailLoginMethod::Code;
+            let subjects: Vec<LoginSubject> = [Some(LoginSubject::Account(&email)), ip]
+                .into_iter()
+                .flatten()
+                .collect();
+            check_locked(&state, &client, method, None, &subjects).await?;
+
+            (
+                consume_login_code(&state, &client, &email, &code).await,
+                method,
+            )
+        }
+    };
+    // The account may have been purged since the link or code was sent.
+    let user = async {
+        user::Entity::find_by_id(uid?)
+            .one(&state.db)
+            .await
+            .warn_err()?
+            .ok_or(Error::NotFound)
+    };
+    let user = user.await.inspect_err(|_| {
+        LOGINS
+            .with_label_values(&[method.metric_label(), "failed"])
+            .inc();
+    })?;
+
+    // Links do not name the account, so its lockout can only be checked now.
+    let account = [LoginSubject::Account(&user.email)];
+    check_locked(&state, &client, method, Some(user.id), &account).await?;
+
+    if let Err(e) = suspension::check(&state.db, store, user.id).await {
+        LOGINS
+            .with_label_values(&[method.metric_label(), "suspended"])
+            .inc();
+        AuditEvent::new(EventType::EmailLoginFailed)
+            .subject(user.id)
+            .client(&client)
+            .details(json!({ "reason": "suspended" }))
+            .record(&state.db)
+            .await;
+        return Err(e);
+    }
+
+    let token = Token::issue(&state, user.id, &client, None).await?;
+    LOGINS
+        .with_label_values(&[method.metric_label(), "success"])
+        .inc();
+    TOKENS_ISSUED.with_label_values(&["email_login"]).inc();
+
+    AuditEvent::new(EventType::EmailLogin)
+        .user(user.id)
+        .client(&client)
+        .details(json!({ "method": method }))
+        .record(&state.db)
+        .await;
+
+    // A failed alert must not fail the sign-in itself.
+    let _ = check_new_device(&state, &user, &client, &token.session).await;
+    cancel_deletion(&state, &user, &client).await?;
+
+    Ok(Json(token))
+}
+
+/// Fails with [`Error::TooManyAttempts`] if any of `subjects` is locked out, recording the
+/// refused login.
+async fn check_locked(
+    state: &AppState,
+    client: &ClientInfo,
+    method: EmailLoginMethod,
+    uid: Option<i32>,
+    subjects: &[LoginSubject<'_>],
+) -> Result<(), Error> {
+    let Err(e) = check_lockout(state.store.as_ref(), subjects).await else {
+        return Ok(());
+    };
+
+    LOGINS
+        .with_label_values(&[method.metric_label(), "locked"])
+        .inc();
+    let mut event = AuditEvent::new(EventType::EmailLoginFailed)
+        .client(client)
+        .details(json!({ "reason": "locked_out" }));
+    if let Some(uid) = uid {
+        event = event.subject(uid);
+    }
+    event.record(&state.db).await;
+
+    Err(e)
+}
+
+/// Checks a login code sent to `email` and consumes it, returning the user it was sent to.
+///
+/// Each wrong guess is counted, and the code is discarded after too many of them.
+pub(crate) async fn consume_login_code(
+    state: &AppState,
+    client: &ClientInfo,
+    email: &str,
+    code: &str,
+) -> Result<i32, Error> {
+    match state
+        .store
+        .check_login_code(
+            email,
+            code,
+            state.config.tokens.email_login_max_code_attempts,
+        )
+        .await?
+    {
+        CodeCheck::Correct { uid } => Ok(uid),
+        CodeCheck::Incorrect { uid, attempts } => {
+            AuditEvent::new(EventType::EmailLoginFailed)
+                .subject(uid)
+                .client(client)
+                .details(json!({ "attempts": attempts }))
+                .record(&state.db)
+                .await;
+
+            Err(Error::IncorrectCode)
+        }
+        CodeCheck::Missing => Err(Error::NotFound),
+    }
+}
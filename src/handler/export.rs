@@ -0,0 +1,238 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+};
+use chrono::Utc;
+use lettre::message::{header as mail_header, SinglePart};
+use lettre::Message;
+use sea_orm::{ColumnTrait, Condition, EntityTrait, QueryFilter, QueryOrder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    data::{credential::Claims, error::Error, session::ClientInfo},
+    entity::{audit_event, audit_event::EventType, known_device, user},
+    handler::{openapi::errors, profile::MyProfile},
+    store::generate_refresh_token,
+    utils::{
+        audit::{recorded_by_email, AuditEvent},
+        db::StanderizeError,
+    },
+    AppState,
+};
+
+/// What is kept under `export:{token}` until the archive is downloaded or expires.
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredExport {
+    uid: i32,
+    archive: String,
+}
+
+#[derive(Template)]
+#[template(path = "data_export.html")]
+struct DataExportTemplate<'a> {
+    name: &'a str,
+    download_link: &'a str,
+    valid_hours: u64,
+}
+
+errors!(ExportErrors:
+    InvalidToken,
+    ExpiredToken,
+    AccountSuspended,
+    Forbidden,
+    RateLimited,
+    InternalServerError,
+);
+
+/// Starts assembling an archive of everything stored about the current user.
+///
+/// A download link to the archive is emailed once it is ready. Only one export can be
+/// requested per `tokens.export_cooldown_seconds`, unless building it failed.
+#[utoipa::path(
+    post,
+    path = "/v0/me/export",
+    tag = "account",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The download link is emailed"),
+        ExportErrors,
+    ),
+)]
+pub async fn export(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    claims: Claims<0>,
+) -> Result<(), Error> {
+    if claims.scopes.is_some() {
+        return Err(Error::Forbidden);
+    }
+
+    let cooldown_key = format!("export_cooldown:{}", claims.uid);
+    let cooldown = Duration::from_secs(state.config.tokens.export_cooldown_seconds);
+    if !state.store.set_nx(&cooldown_key, "1", cooldown).await? {
+        let ttl = state.store.ttl(&cooldown_key).await?.unwrap_or_default();
+        return Err(Error::RateLimited {
+            retry_after: ttl.as_secs().max(1),
+        });
+    }
+
+    AuditEvent::new(EventType::DataExportRequested)
+        .user(claims.uid)
+        .client(&client)
+        .record(&state.db)
+        .await;
+
+    // Built while the request is held, so that a shutdown waits for it to finish.
+    if let Err(e) = build_export(&state, claims.uid).await {
+        tracing::warn!("failed to export data of user {}", claims.uid);
+        state.store.delete(&cooldown_key).await?;
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+async fn build_export(state: &AppState, uid: i32) -> Result<(), Error> {
+    let user = user::Entity::find_by_id(uid)
+        .one(&state.db)
+        .await
+        .warn_err()?
+        .ok_or(Error::NotFound)?;
+
+    let known_devices = known_device::Entity::find()
+        .filter(known_device::Column::UserId.eq(uid))
+        .all(&state.db)
+        .await
+        .warn_err()?;
+
+    let audit_events = audit_event::Entity::find()
+        .filter(
+            Condition::any()
+                .add(audit_event::Column::SubjectId.eq(uid))
+                .add(recorded_by_email(&user.email)),
+        )
+        .order_by_asc(audit_event::Column::Id)
+        .all(&state.db)
+        .await
+        .warn_err()?;
+
+    let sessions = state.store.list_sessions(uid, None).await?;
+
+    let archive = json!({
+        "generated_at": Utc::now().naive_utc(),
+        "profile": MyProfile::from(user.clone()),
+        "sessions": sessions,
+        "known_devices": known_devices,
+        "audit_events": audit_events,
+    });
+
+    let ttl = Duration::from_secs(state.config.tokens.export_ttl_seconds);
+    let token = generate_refresh_token();
+    let stored = json!(StoredExport {
+        uid,
+        archive: archive.to_string(),
+    });
+    state
+        .store
+        .set(
+            &format!("export:{}", &token),
+            &stored.to_string(),
+            Some(ttl),
+        )
+        .await?;
+
+    let message = Message::builder()
+        .subject("你的WebSxz数据导出已完成")
+        .from(state.mailer.from.clone())
+        .to(user.email.parse().map_err(|e| {
+            tracing::warn!("stored email illegal: {}", e);
+            Error::InternalServerError
+        })?)
+        .singlepart(
+            SinglePart::builder()
+                .header(mail_header::ContentType::TEXT_HTML)
+                .body(
+                    DataExportTemplate {
+                        name: &user.name,
+                        download_link: &state.config.server.link(&format!("/me/export/{}", token)),
+                        valid_hours: ttl.as_secs() / 60 / 60,
+                    }
+                    .render()
+                    .map_err(|e| {
+                        tracing::warn!("email template render failed: {}", e);
+                        Error::InternalServerError
+                    })?,
+                ),
+        )
+        .map_err(|e| {
+            tracing::warn!("failed to build email: {}", e);
+            Error::InternalServerError
+        })?;
+
+    state.mailer.send(message).await
+}
+
+errors!(DownloadExportErrors:
+    InvalidToken,
+    ExpiredToken,
+    AccountSuspended,
+    Forbidden,
+    NotFound,
+    InternalServerError,
+);
+
+/// Downloads an archive built by [`export`]. Only the user it was built for may fetch it.
+#[utoipa::path(
+    get,
+    path = "/v0/me/export/{token}",
+    tag = "account",
+    params(("token" = String, Path, description = "The token of the emailed download link")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The archive, as an attachment", body = Object),
+        DownloadExportErrors,
+    ),
+)]
+pub async fn download_export(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    claims: Claims<0>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    if claims.scopes.is_some() {
+        return Err(Error::Forbidden);
+    }
+
+    let StoredExport { uid, archive } = state
+        .store
+        .get(&format!("export:{}", &token))
+        .await?
+        .and_then(|stored| serde_json::from_str(&stored).ok())
+        .ok_or(Error::NotFound)?;
+    if uid != claims.uid {
+        return Err(Error::NotFound);
+    }
+
+    AuditEvent::new(EventType::DataExportDownloaded)
+        .user(claims.uid)
+        .client(&client)
+        .record(&state.db)
+        .await;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"websxz-export-{}.json\"", uid),
+            ),
+        ],
+        archive,
+    ))
+}
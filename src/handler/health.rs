@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{data::error::Error, utils::db::StanderizeError, AppState};
+
+#[derive(Serialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    #[serde(rename = "ok")]
+    Ok,
+    #[serde(rename = "failing")]
+    Failing,
+    #[serde(rename = "draining")]
+    Draining,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Check {
+    status: Status,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct Readiness {
+    status: Status,
+    /// Absent while draining, when nothing is checked.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    checks: BTreeMap<&'static str, Check>,
+}
+
+/// Answers as long as the process is up, without looking at any dependency.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, description = "The process is up", body = Object)),
+)]
+pub async fn healthz() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": Status::Ok }))
+}
+
+/// Whether this instance can serve requests: the database and the ephemeral store, and
+/// optionally the SMTP server, must answer within `health.timeout_ms`.
+///
+/// Fails while the server is draining for shutdown, so that no new traffic is routed here.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Every check passed", body = Readiness),
+        (status = 503, description = "A check failed, or the server is draining", body = Readiness),
+    ),
+)]
+pub async fn readyz(state: State<Arc<AppState>>) -> (StatusCode, Json<Readiness>) {
+    if state.draining.load(Ordering::Relaxed) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(Readiness {
+                status: Status::Draining,
+                checks: BTreeMap::new(),
+            }),
+        );
+    }
+
+    let timeout = Duration::from_millis(state.config.health.timeout_ms);
+    let (database, store, smtp) = tokio::join!(
+        check(timeout, async { state.db.ping().await.warn_err() }),
+        check(timeout, state.store.ping()),
+        async {
+            if state.config.health.check_smtp {
+                Some(check(timeout, state.mailer.ping()).await)
+            } else {
+                None
+            }
+        },
+    );
+
+    let mut checks = BTreeMap::from([("database", database), ("store", store)]);
+    if let Some(smtp) = smtp {
+        checks.insert("smtp", smtp);
+    }
+
+    let ready = checks.values().all(|check| check.status == Status::Ok);
+    let (code, status) = if ready {
+        (StatusCode::OK, Status::Ok)
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Status::Failing)
+    };
+
+    (code, Json(Readiness { status, checks }))
+}
+
+async fn check(timeout: Duration, ping: impl Future<Output = Result<(), Error>>) -> Check {
+    let start = Instant::now();
+    let result = tokio::time::timeout(timeout, ping).await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+
+    let (status, error) = match result {
+        Ok(Ok(())) => (Status::Ok, None),
+        Ok(Err(_)) => (Status::Failing, Some("unreachable")),
+        Err(_) => (Status::Failing, Some("timed out")),
+    };
+
+    Check {
+        status,
+        latency_ms,
+        error,
+    }
+}
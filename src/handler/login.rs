@@ -1,105 +1,344 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::data::credential::generate_token;
 use crate::data::error::Error;
+use crate::data::session::ClientInfo;
+use crate::handler::openapi::errors;
+use crate::entity::audit_event::EventType;
 use crate::entity::user;
+use crate::utils::account::cancel_deletion;
+use crate::utils::audit::AuditEvent;
 use crate::utils::captcha::{verify_captcha, Captcha};
+use crate::utils::db::StanderizeError;
+use crate::utils::device::check_new_device;
 use crate::utils::encryption::salt_password;
-use crate::utils::redis::{generate_refresh_token, get_connection, InsertRefreshToken};
+use crate::store::{generate_refresh_token, TokenPurpose};
+use crate::utils::lockout::{self, check_lockout, record_failure, LoginSubject};
+use crate::utils::metrics::{LOGINS, REFRESH_ROTATIONS, TOKENS_ISSUED};
+use crate::utils::suspension;
 use crate::AppState;
+use askama::Template;
 use axum::extract::State;
-use axum::http::HeaderMap;
 use axum::response::IntoResponse;
 use axum::Json;
 use axum_extra::headers::authorization::Bearer;
 use axum_extra::headers::Authorization;
 use axum_extra::TypedHeader;
-use redis::Commands;
+use lettre::message::{header, SinglePart};
+use lettre::Message;
 use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
 
+errors!(LoginErrors:
+    MissingCaptchaToken,
+    InvalidCaptcha,
+    TimeOutOrDuplicateCaptcha,
+    BadRequest,
+    IncorrectEmailOrPassword,
+    TooManyAttempts,
+    RateLimited,
+    AccountSuspended,
+    InternalServerError,
+);
+
+/// Signs in with an email and password.
+///
+/// Repeated failures lock out the account and the IP for a while, and mail the account a
+/// link that lifts its lockout.
+#[utoipa::path(
+    post,
+    path = "/v0/login",
+    tag = "auth",
+    request_body = LoginBody,
+    responses(
+        (status = 200, description = "A new session", body = Token),
+        LoginErrors,
+    ),
+)]
 pub async fn login(
     state: State<Arc<AppState>>,
-    headers: HeaderMap,
+    client: ClientInfo,
     Json(data): Json<LoginBody>,
 ) -> Result<Json<Token>, impl IntoResponse> {
-    let remote_ip = headers
-        .get("x-forwarded-for")
-        .and_then(|value| value.to_str().ok());
+    verify_captcha(&state.config.captcha, data.captcha, client.ip.as_deref()).await?;
 
-    verify_captcha(data.captcha, remote_ip)
-        .await
-        .map_err(Into::into)?;
+    let store = state.store.as_ref();
+    let account = LoginSubject::Account(&data.email);
+    let subjects: Vec<LoginSubject> = [Some(account), client.ip.as_deref().map(LoginSubject::Ip)]
+        .into_iter()
+        .flatten()
+        .collect();
+
+    if let Err(e) = check_lockout(store, &subjects).await {
+        LOGINS.with_label_values(&["password", "locked"]).inc();
+        AuditEvent::new(EventType::LoginFailed)
+            .client(&client)
+            .details(json!({ "email": data.email, "reason": "locked_out" }))
+            .record(&state.db)
+            .await;
+        return Err(e);
+    }
 
-    if let Some(user) = user::Entity::find()
-        .filter(user::Column::Email.eq(data.email))
+    let user = user::Entity::find()
+        .filter(user::Column::Email.eq(&data.email))
         .one(&state.db)
         .await
-        .map_err(|e| {
-            tracing::warn!("database error: {}", e);
-            Error::InternalServerError
-        })?
-    {
-        if user.salted_password != salt_password(&data.hashed_password, &user.salt) {
-            return Err(Error::IncorrectEmailOrPassword);
+        .warn_err()?;
+
+    if let Some(user) = &user {
+        if user.salted_password == salt_password(&data.hashed_password, &user.salt) {
+            lockout::reset(store, account).await?;
+
+            if let Err(e) = suspension::check(&state.db, store, user.id).await {
+                LOGINS.with_label_values(&["password", "suspended"]).inc();
+                AuditEvent::new(EventType::LoginFailed)
+                    .subject(user.id)
+                    .client(&client)
+                    .details(json!({ "email": data.email, "reason": "suspended" }))
+                    .record(&state.db)
+                    .await;
+                return Err(e);
+            }
+
+            let token = Token::issue(&state, user.id, &client, data.device_name).await?;
+            LOGINS.with_label_values(&["password", "success"]).inc();
+            TOKENS_ISSUED.with_label_values(&["password"]).inc();
+            AuditEvent::new(EventType::Login)
+                .user(user.id)
+                .client(&client)
+                .details(json!({ "method": "password" }))
+                .record(&state.db)
+                .await;
+            // A failed alert must not fail the sign-in itself.
+            let _ = check_new_device(&state, user, &client, &token.session).await;
+            cancel_deletion(&state, user, &client).await?;
+
+            return Ok(Json(token));
         }
+    }
 
-        let mut conn = get_connection(&state.redis)?;
-        let refresh_token = generate_refresh_token();
-        conn.insert_refresh_token(&refresh_token, user.id)?;
+    LOGINS.with_label_values(&["password", "failed"]).inc();
+    let mut event = AuditEvent::new(EventType::LoginFailed)
+        .client(&client)
+        .details(json!({ "email": data.email, "reason": "incorrect_credentials" }));
+    if let Some(user) = &user {
+        event = event.subject(user.id);
+    }
+    event.record(&state.db).await;
 
-        return Ok(Json(Token {
-            token: generate_token(user.id)?,
-            refresh_token,
-        }));
+    for subject in subjects {
+        let Some(duration) = record_failure(store, &state.config.lockout, subject).await? else {
+            continue;
+        };
+
+        let locked = match subject {
+            LoginSubject::Account(email) => json!({ "email": email }),
+            LoginSubject::Ip(ip) => json!({ "ip": ip }),
+        };
+        let mut event = AuditEvent::new(EventType::LoginLocked)
+            .client(&client)
+            .details(json!({ "locked": locked, "duration": duration }));
+        if let (LoginSubject::Account(_), Some(user)) = (subject, &user) {
+            event = event.subject(user.id);
+            // The lockout holds either way, so a lost email must not turn into a 500.
+            if let Err(e) = send_unlock_email(&state, &user.email).await {
+                tracing::warn!("failed to send unlock email: {:?}", e);
+            }
+        }
+        event.record(&state.db).await;
     }
+
     Err(Error::IncorrectEmailOrPassword)
 }
 
-pub async fn refresh_token(
-    state: State<Arc<AppState>>,
-    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-) -> Result<Json<Token>, impl IntoResponse> {
-    let key = format!("refresh:{}", bearer.token());
-    let mut conn = get_connection(&state.redis)?;
+/// Mails a link that lifts the lockout of `email`, at most once per hour.
+async fn send_unlock_email(state: &AppState, email: &str) -> Result<(), Error> {
+    let tokens = &state.config.tokens;
+    let key = format!("login_unlock_sent:{}", email);
+    let cooldown = Duration::from_secs(tokens.unlock_email_cooldown_seconds);
+    if !state.store.set_nx(&key, "1", cooldown).await? {
+        return Ok(());
+    }
 
-    let exist: bool = conn.exists(&key).map_err(|e| {
-        tracing::warn!("failed to judge existence of refresh token: {}", e);
-        Error::InternalServerError
-    })?;
+    let token = generate_refresh_token();
+    state
+        .store
+        .store_token(
+            TokenPurpose::LoginUnlock,
+            &token,
+            email,
+            Duration::from_secs(tokens.unlock_link_ttl_seconds),
+        )
+        .await?;
 
-    if exist {
-        let id = conn.get(&key).map_err(|e| {
-            tracing::warn!("failed to get value: {}", e);
+    let message = Message::builder()
+        .subject("你的WebSxz账号已被暂时锁定")
+        .from(state.mailer.from.clone())
+        .to(email.parse().map_err(|e| {
+            tracing::warn!("stored email illegal: {}", e);
             Error::InternalServerError
-        })?;
-
-        let _: () = conn.del(&key).map_err(|e| {
-            tracing::warn!("failed to delete key: {}", e);
+        })?)
+        .singlepart(
+            SinglePart::builder()
+                .header(header::ContentType::TEXT_HTML)
+                .body(
+                    AccountUnlockTemplate {
+                        unlock_link: &state
+                            .config
+                            .server
+                            .link(&format!("/login/unlock?token={}", token)),
+                    }
+                    .render()
+                    .map_err(|e| {
+                        tracing::warn!("email template render failed: {}", e);
+                        Error::InternalServerError
+                    })?,
+                ),
+        )
+        .map_err(|e| {
+            tracing::warn!("failed to build email: {}", e);
             Error::InternalServerError
         })?;
 
-        let r = generate_refresh_token();
-        let _: () = conn.insert_refresh_token(&r, id)?;
+    state.mailer.send(message).await
+}
+
+errors!(UnlockErrors: NotFound, InternalServerError);
+
+/// Lifts the lockout of the account an unlock link was sent to.
+#[utoipa::path(
+    post,
+    path = "/v0/login/unlock",
+    tag = "auth",
+    request_body = UnlockBody,
+    responses(
+        (status = 200, description = "The account is unlocked"),
+        UnlockErrors,
+    ),
+)]
+pub async fn unlock(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    Json(data): Json<UnlockBody>,
+) -> Result<(), Error> {
+    let email = state
+        .store
+        .consume_token(TokenPurpose::LoginUnlock, &data.token)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    lockout::reset(state.store.as_ref(), LoginSubject::Account(&email)).await?;
+
+    AuditEvent::new(EventType::LoginUnlocked)
+        .client(&client)
+        .details(json!({ "email": email }))
+        .record(&state.db)
+        .await;
+
+    Ok(())
+}
+
+errors!(RefreshErrors: Unauthorized, RateLimited, AccountSuspended, InternalServerError);
+
+/// Exchanges a refresh token for a new token pair. The refresh token is rotated, so the
+/// one sent can no longer be used.
+#[utoipa::path(
+    get,
+    path = "/v0/refresh",
+    tag = "auth",
+    security(("refresh_token" = [])),
+    responses(
+        (status = 200, description = "The new token pair", body = Token),
+        RefreshErrors,
+    ),
+)]
+pub async fn refresh_token(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<Token>, impl IntoResponse> {
+    if let Some((id, session, refresh_token)) = state
+        .store
+        .rotate_refresh_token(bearer.token(), &client)
+        .await?
+    {
+        tracing::Span::current().record("user_id", id);
+        if let Err(e) = suspension::check(&state.db, state.store.as_ref(), id).await {
+            // The rotated refresh token is never handed out, so rather than leave the
+            // session behind unreachable, it is ended.
+            state.store.revoke_session(id, &session).await?;
+            return Err(e);
+        }
+        REFRESH_ROTATIONS.with_label_values(&["rotated"]).inc();
+        TOKENS_ISSUED.with_label_values(&["refresh_token"]).inc();
+
+        AuditEvent::new(EventType::TokenRefreshed)
+            .user(id)
+            .client(&client)
+            .details(json!({ "session": session }))
+            .record(&state.db)
+            .await;
 
         return Ok(Json(Token {
-            token: generate_token(id)?,
-            refresh_token: r,
+            token: generate_token(&state, id, &session)?,
+            refresh_token,
+            session,
         }));
     }
 
+    REFRESH_ROTATIONS.with_label_values(&["rejected"]).inc();
     Err(Error::Unauthorized)
 }
 
-#[derive(Serialize, Debug)]
+/// An access token and the refresh token of its session.
+#[derive(Serialize, ToSchema, Debug)]
 pub struct Token {
     token: String,
     refresh_token: String,
+    #[serde(skip)]
+    pub(crate) session: String,
 }
 
-#[derive(Debug, Deserialize)]
+impl Token {
+    /// Starts a new session for `uid` and generates its token pair.
+    pub(crate) async fn issue(
+        state: &AppState,
+        uid: i32,
+        client: &ClientInfo,
+        device_name: Option<String>,
+    ) -> Result<Self, Error> {
+        tracing::Span::current().record("user_id", uid);
+        let (session, refresh_token) = state
+            .store
+            .create_session(uid, client, device_name)
+            .await?;
+
+        Ok(Token {
+            token: generate_token(state, uid, &session)?,
+            refresh_token,
+            session,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct LoginBody {
     email: String,
     hashed_password: String,
     captcha: Captcha,
+    device_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UnlockBody {
+    token: String,
+}
+
+#[derive(Template)]
+#[template(path = "account_unlock.html")]
+struct AccountUnlockTemplate<'a> {
+    unlock_link: &'a str,
 }
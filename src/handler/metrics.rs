@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::{
+    data::{
+        credential::{Admin, Permission},
+        error::Error,
+    },
+    utils::metrics::render,
+    AppState,
+};
+
+/// Every metric in the Prometheus text format.
+pub async fn metrics(state: State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(&state).await,
+    )
+}
+
+/// [`metrics`] for admins only, for when they are served next to the API.
+pub async fn admin_metrics(
+    state: State<Arc<AppState>>,
+    admin: Admin,
+) -> Result<impl IntoResponse, Error> {
+    admin.require(Permission::ViewMetrics)?;
+    Ok(metrics(state).await)
+}
@@ -1,4 +1,15 @@
 pub mod login;
 pub mod register;
 pub mod oauth;
-pub mod profile;
\ No newline at end of file
+pub mod profile;
+pub mod email_login;
+pub mod session;
+pub mod audit;
+pub mod account;
+pub mod export;
+pub mod username;
+pub mod avatar;
+pub mod admin;
+pub mod health;
+pub mod metrics;
+pub mod openapi;
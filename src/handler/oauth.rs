@@ -1,43 +1,72 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::{
     extract::{Query, State},
     Json,
 };
-use redis::Commands;
 use sea_orm::EntityTrait;
 use serde::Deserialize;
+use serde_json::json;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{
     data::{
         credential::{generate_oauth_token, Claims, Scope},
         error::Error,
+        session::ClientInfo,
     },
-    entity::oauth_client,
-    utils::{
-        db::StanderizeError,
-        redis::{generate_refresh_token, get_connection},
-    },
+    entity::{audit_event::EventType, oauth_client},
+    handler::openapi::errors,
+    store::{generate_refresh_token, AuthorizationCode},
+    utils::{audit::AuditEvent, db::StanderizeError, metrics::TOKENS_ISSUED},
     AppState,
 };
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
 pub struct OAuthParams {
+    /// The scopes asked for, separated by spaces.
     scopes: String,
     redirect_uri: String,
-    client_id: u32,
+    client_id: i32,
     state: String,
     response_type: ResponseType,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[derive(Deserialize, ToSchema, Debug, PartialEq, Eq)]
 pub enum ResponseType {
     #[serde(rename = "code")]
     Code,
 }
 
+errors!(OAuthErrors:
+    InvalidToken,
+    ExpiredToken,
+    AccountSuspended,
+    BadRequest,
+    RateLimited,
+    InternalServerError,
+);
+
+/// Authorizes a client on behalf of the signed-in user.
+///
+/// Answers with the `redirect_uri` to send the user back to, carrying the `state` and an
+/// authorization code for [`exchange_token`].
+#[utoipa::path(
+    get,
+    path = "/v0/oauth",
+    tag = "oauth",
+    params(OAuthParams),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The URL to redirect to", body = String),
+        OAuthErrors,
+    ),
+)]
 pub async fn oauth(
     state: State<Arc<AppState>>,
+    client: ClientInfo,
     Query(params): Query<OAuthParams>,
     claims: Claims<0>,
 ) -> Result<Json<String>, Error> {
@@ -48,48 +77,60 @@ pub async fn oauth(
         state: req_state,
         response_type,
     } = params;
+    tracing::Span::current().record("client_id", client_id);
 
     if response_type != ResponseType::Code {
         return Err(Error::BadRequest);
     }
 
-    let mut conn = get_connection(&state.redis)?;
-
     let code = generate_refresh_token();
-    let key = format!("oauth:{}", &code);
-
-    let _: () = redis::pipe()
-        .atomic()
-        .hset_multiple(
-            &key,
-            &[
-                ("client_id", client_id.to_string()),
-                ("scopes", scopes),
-                ("uid", claims.uid.to_string()),
-            ],
-        )
-        .ignore()
-        .expire(&key, 5 * 60)
-        .ignore()
-        .query(&mut conn)
-        .map_err(|e| {
-            tracing::warn!("redis error: {}", e);
-            Error::InternalServerError
-        })?;
+    let grant = AuthorizationCode {
+        client_id,
+        scopes: scopes.clone(),
+        uid: claims.uid,
+    };
+    let ttl = Duration::from_secs(state.config.auth.authorization_code_ttl_seconds);
+    state
+        .store
+        .store_authorization_code(&code, &grant, ttl)
+        .await?;
+
+    AuditEvent::new(EventType::OAuthAuthorized)
+        .user(claims.uid)
+        .client(&client)
+        .client_id(client_id)
+        .details(json!({ "scopes": scopes, "redirect_uri": redirect_uri }))
+        .record(&state.db)
+        .await;
 
     Ok(Json(
-        redirect_uri + &format!("?state={}&code={}", req_state, code),
+        redirect_uri + format!("?state={}&code={}", req_state, code).as_str(),
     ))
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
 pub struct ExchangeTokenParams {
     code: String,
     client_secret: String,
 }
 
+errors!(ExchangeTokenErrors: NotFound, BadRequest, Unauthorized, InternalServerError);
+
+/// Exchanges an authorization code for an access token carrying the scopes granted.
+#[utoipa::path(
+    get,
+    path = "/v0/oauth/token",
+    tag = "oauth",
+    params(ExchangeTokenParams),
+    responses(
+        (status = 200, description = "The access token", body = String),
+        ExchangeTokenErrors,
+    ),
+)]
 pub async fn exchange_token(
     state: State<Arc<AppState>>,
+    client_info: ClientInfo,
     Query(params): Query<ExchangeTokenParams>,
 ) -> Result<Json<String>, Error> {
     let ExchangeTokenParams {
@@ -97,36 +138,42 @@ pub async fn exchange_token(
         client_secret,
     } = params;
 
-    let key = format!("oauth:{}", &code);
-    let mut conn = get_connection(&state.redis)?;
-    let v: (Option<String>, Option<String>, Option<String>) = conn
-        .hget(&key, &["client_id", "scopes", "uid"])
-        .warn_err()?;
-
-    if let (Some(client_id), Some(scopes), Some(uid)) = v {
-        let client = oauth_client::Entity::find_by_id(client_id.parse::<u32>().debug_err()?)
-            .one(&state.db)
-            .await
-            .warn_err()?;
-        if let Some(client) = client {
-            if client.client_secret != client_secret {
-                return Err(Error::Unauthorized);
-            }
-
-            let scopes: Vec<Scope> = scopes
-                .split(' ')
-                .filter_map(|selection| serde_json::from_str(selection).ok())
-                .collect();
-
-            let token = generate_oauth_token(uid.parse::<u32>().debug_err()?, scopes)?;
-
-            let _: () = conn.del(&key).warn_err()?;
-
-            return Ok(Json(token));
-        }
-
-        return Err(Error::BadRequest);
+    // Codes are single use, so a failed exchange spends the code as well.
+    let grant = state
+        .store
+        .consume_authorization_code(&code)
+        .await?
+        .ok_or(Error::NotFound)?;
+    tracing::Span::current()
+        .record("user_id", grant.uid)
+        .record("client_id", grant.client_id);
+
+    let client = oauth_client::Entity::find_by_id(grant.client_id)
+        .one(&state.db)
+        .await
+        .warn_err()?
+        .ok_or(Error::BadRequest)?;
+    if client.client_secret != client_secret {
+        return Err(Error::Unauthorized);
     }
 
-    Err(Error::NotFound)
+    let scopes: Vec<Scope> = grant
+        .scopes
+        .split(' ')
+        .filter_map(|selection| serde_json::from_str(selection).ok())
+        .filter(|scope| client.official || *scope != Scope::Admin)
+        .collect();
+
+    let token = generate_oauth_token(&state, grant.uid, scopes.clone())?;
+    TOKENS_ISSUED.with_label_values(&["authorization_code"]).inc();
+
+    AuditEvent::new(EventType::OAuthTokenExchanged)
+        .subject(grant.uid)
+        .client(&client_info)
+        .client_id(client.client_id)
+        .details(json!({ "scopes": scopes }))
+        .record(&state.db)
+        .await;
+
+    Ok(Json(token))
 }
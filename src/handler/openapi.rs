@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+
+use axum::http::header;
+use axum::response::IntoResponse;
+use lazy_static::lazy_static;
+use utoipa::openapi::security::{
+    AuthorizationCode, Flow, HttpAuthScheme, HttpBuilder, OAuth2, Scopes, SecurityScheme,
+};
+use utoipa::openapi::{ContentBuilder, Ref, RefOr, Response, ResponseBuilder};
+use utoipa::{Modify, OpenApi};
+
+use crate::data::error::{ErrorCode, Problem, PROBLEM_JSON};
+use crate::handler;
+
+/// Declares a type documenting the errors a route can respond with, for the `responses`
+/// of its `#[utoipa::path]`.
+macro_rules! errors {
+    ($name:ident: $($code:ident),+ $(,)?) => {
+        pub struct $name;
+
+        impl utoipa::IntoResponses for $name {
+            fn responses() -> std::collections::BTreeMap<
+                String,
+                utoipa::openapi::RefOr<utoipa::openapi::Response>,
+            > {
+                $crate::handler::openapi::error_responses(&[
+                    $($crate::data::error::ErrorCode::$code),+
+                ])
+            }
+        }
+    };
+}
+pub(crate) use errors;
+
+/// One problem response per status, listing the codes it is given for.
+pub fn error_responses(codes: &[ErrorCode]) -> BTreeMap<String, RefOr<Response>> {
+    let mut by_status: BTreeMap<u16, Vec<ErrorCode>> = BTreeMap::new();
+    for code in codes {
+        by_status.entry(code.status().as_u16()).or_default().push(*code);
+    }
+
+    by_status
+        .into_iter()
+        .map(|(status, codes)| {
+            let codes: Vec<String> = codes.iter().map(|code| format!("`{:?}`", code)).collect();
+            let response = ResponseBuilder::new()
+                .description(codes.join(", "))
+                .content(
+                    PROBLEM_JSON,
+                    ContentBuilder::new()
+                        .schema(Some(Ref::from_schema_name("Problem")))
+                        .build(),
+                )
+                .build();
+            (status.to_string(), response.into())
+        })
+        .collect()
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "WebSxz Accounts",
+        description = "Accounts, sessions and OAuth for WebSxz.\n\n\
+            Errors are RFC 7807 problem documents, whose `code` tells them apart and whose \
+            `detail` is written in the language asked for by `Accept-Language`."
+    ),
+    paths(
+        handler::login::login,
+        handler::login::unlock,
+        handler::login::refresh_token,
+        handler::email_login::login_email,
+        handler::email_login::verify_login_email,
+        handler::register::register,
+        handler::register::resend,
+        handler::register::status,
+        handler::register::verify,
+        handler::username::available,
+        handler::username::change_username,
+        handler::oauth::oauth,
+        handler::oauth::exchange_token,
+        handler::profile::me,
+        handler::profile::edit,
+        handler::profile::user,
+        handler::profile::user_by_username,
+        handler::profile::lookup_users,
+        handler::account::delete_me,
+        handler::avatar::upload_avatar,
+        handler::avatar::delete_avatar,
+        handler::session::sessions,
+        handler::session::delete_sessions,
+        handler::session::delete_session,
+        handler::session::revoke_by_link,
+        handler::audit::activity,
+        handler::export::export,
+        handler::export::download_export,
+        handler::audit::events,
+        handler::admin::users,
+        handler::admin::get_user,
+        handler::admin::edit_user,
+        handler::admin::revoke_user_sessions,
+        handler::admin::suspend,
+        handler::admin::unsuspend,
+        handler::admin::force_verify,
+        handler::health::healthz,
+        handler::health::readyz,
+    ),
+    components(schemas(Problem)),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "auth", description = "Signing in and refreshing tokens"),
+        (name = "registration", description = "Creating accounts"),
+        (name = "profile", description = "Profiles of the signed-in user and of others"),
+        (name = "account", description = "Sessions, activity, exports and deletion"),
+        (name = "oauth", description = "Authorizing third-party clients"),
+        (name = "admin", description = "Moderation, by users with an administrative role"),
+        (name = "health", description = "Probes for orchestrators"),
+    ),
+)]
+pub struct ApiDoc;
+
+/// First-party clients use `bearer` tokens, which pass every scope check. Tokens issued to
+/// OAuth clients only carry the scopes they were granted.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .description(Some("An access token from signing in."))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "refresh_token",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("A refresh token from signing in."))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "oauth2",
+            SecurityScheme::OAuth2(OAuth2::with_description(
+                [Flow::AuthorizationCode(AuthorizationCode::new(
+                    "/v0/oauth",
+                    "/v0/oauth/token",
+                    Scopes::from_iter([
+                        ("profile.read", "Read the profile"),
+                        ("profile.write", "Edit the profile, username and avatar"),
+                        ("profile.details", "Read the private profile fields"),
+                        ("sessions.read", "List sessions"),
+                        ("sessions.write", "Revoke sessions"),
+                        ("activity.read", "Read the account activity"),
+                        ("users.read", "Look up other users"),
+                        ("admin", "Act with the administrative role of the user"),
+                    ]),
+                ))],
+                "Authorization is asked for by the signed-in user through `/v0/oauth`, which \
+                 answers with the URL to redirect to. The code is then exchanged with a GET \
+                 to `/v0/oauth/token` carrying the client secret.",
+            )),
+        );
+    }
+}
+
+lazy_static! {
+    static ref DOCUMENT: String = ApiDoc::openapi()
+        .to_json()
+        .expect("the OpenAPI document serializes");
+}
+
+/// This OpenAPI document.
+pub async fn openapi() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], DOCUMENT.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::DOCUMENT;
+
+    /// Mounted routes that are deliberately left out of the document.
+    const UNDOCUMENTED: &[&str] = &["/metrics", "/openapi.json"];
+
+    /// The routers built in `main.rs` and where each is mounted.
+    const ROUTERS: &[(&str, &str)] = &[
+        ("let admin", "/v0/admin"),
+        ("let v0", "/v0"),
+        ("let metrics_app", ""),
+        ("let mut app", ""),
+    ];
+
+    /// The method and path of every route mounted in `main.rs`, in OpenAPI notation.
+    fn mounted_routes() -> Vec<(&'static str, String)> {
+        let mut routes = Vec::new();
+        let mut prefix = "";
+        for (i, chunk) in include_str!("../main.rs").split(".route(").enumerate() {
+            if i > 0 {
+                let call = &chunk[..chunk.find(';').unwrap_or(chunk.len())];
+                let (_, path) = call.split_once('"').expect("route paths are literals");
+                let (path, handlers) = path.split_once('"').unwrap();
+                let path: Vec<String> = path
+                    .split('/')
+                    .map(|segment| match segment.strip_prefix(':') {
+                        Some(name) => format!("{{{}}}", name),
+                        None => segment.to_string(),
+                    })
+                    .collect();
+                let path = format!("{}{}", prefix, path.join("/"));
+
+                for method in ["get", "post", "put", "patch", "delete"] {
+                    let mounted = handlers
+                        .match_indices(&format!("{}(", method))
+                        .any(|(at, _)| {
+                            !handlers[..at].ends_with(|c: char| c.is_alphanumeric() || c == '_')
+                        });
+                    if mounted {
+                        routes.push((method, path.clone()));
+                    }
+                }
+            }
+
+            if let Some((_, router)) = ROUTERS
+                .iter()
+                .filter_map(|(marker, router)| chunk.rfind(marker).map(|at| (at, *router)))
+                .max_by_key(|(at, _)| *at)
+            {
+                prefix = router;
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn documents_every_mounted_route() {
+        let document: Value = serde_json::from_str(&DOCUMENT).unwrap();
+        let routes = mounted_routes();
+        assert!(routes.len() > 30, "too few routes found: {:?}", routes);
+
+        let missing: Vec<_> = routes
+            .iter()
+            .filter(|(_, path)| !UNDOCUMENTED.contains(&path.as_str()))
+            .filter(|(method, path)| document["paths"][path][method].is_null())
+            .collect();
+        assert!(missing.is_empty(), "undocumented routes: {:?}", missing);
+    }
+}
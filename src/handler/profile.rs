@@ -1,21 +1,52 @@
 use std::sync::Arc;
 
-use axum::{extract::State, Json};
-use chrono::NaiveDateTime;
-use sea_orm::{ActiveModelTrait, EntityTrait, Set};
-use serde::{Deserialize, Serialize};
-use validator::Validate;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
 
 use crate::{
     data::{
         credential::{scopes, Claims, Scope},
         error::Error,
+        profile::{FieldVisibility, ProfileDetails, Viewer, Visibility},
+        session::ClientInfo,
+    },
+    entity::{audit_event::EventType, user},
+    handler::openapi::errors,
+    utils::{
+        audit::AuditEvent, avatar::Avatar, db::StanderizeError, suspension, username::resolve,
     },
-    entity::user,
-    utils::db::StanderizeError,
     AppState,
 };
 
+errors!(MeErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    NotFound,
+    InternalServerError,
+);
+
+/// First-party tokens see every field, OAuth clients only what the user shares with them.
+#[utoipa::path(
+    get,
+    path = "/v0/me",
+    tag = "profile",
+    security(("bearer" = []), ("oauth2" = ["profile.read"])),
+    responses(
+        (status = 200, description = "The profile of the current user", body = MyProfile),
+        MeErrors,
+    ),
+)]
 pub async fn me(
     state: State<Arc<AppState>>,
     claims: Claims<{ scopes(&[Scope::ProfileRead]) }>,
@@ -26,51 +57,369 @@ pub async fn me(
         .warn_err()?
         .ok_or(Error::NotFound)?;
 
-    Ok(Json(MyProfile {
-        email: user.email,
-        name: user.name,
-        id: user.id,
-        avatar: user.avatar,
-        created_at: user.created_at,
-        updated_at: user.updated_at,
+    let viewer = match &claims.scopes {
+        None => Viewer::Owner,
+        Some(s) if s.contains(&Scope::ProfileDetails) => Viewer::Client,
+        Some(_) => Viewer::Public,
+    };
+
+    Ok(Json(MyProfile::new(user, viewer)))
+}
+
+errors!(UserErrors: NotFound, RateLimited, InternalServerError);
+
+/// `user`, unless it is hidden from others for being suspended or scheduled for deletion.
+async fn shown(db: &DatabaseConnection, user: Option<user::Model>) -> Result<user::Model, Error> {
+    let user = user
+        .filter(|user| user.deletion_scheduled_at.is_none())
+        .ok_or(Error::NotFound)?;
+    if suspension::active_suspension(db, user.id).await?.is_some() {
+        return Err(Error::NotFound);
+    }
+
+    Ok(user)
+}
+
+/// The public profile of any user.
+#[utoipa::path(
+    get,
+    path = "/v0/users/{id}",
+    tag = "profile",
+    params(("id" = i32, Path, description = "The id of the user")),
+    responses(
+        (status = 200, description = "The public profile", body = PublicProfile),
+        UserErrors,
+    ),
+)]
+pub async fn user(
+    state: State<Arc<AppState>>,
+    Path(id): Path<i32>,
+) -> Result<Json<PublicProfile>, Error> {
+    let user = user::Entity::find_by_id(id)
+        .one(&state.db)
+        .await
+        .warn_err()?;
+
+    Ok(Json(shown(&state.db, user).await?.into()))
+}
+
+/// The public profile of the user a handle belongs to, following renamed handles.
+#[utoipa::path(
+    get,
+    path = "/v0/users/by-username/{username}",
+    tag = "profile",
+    params(("username" = String, Path, description = "A current or recently renamed handle")),
+    responses(
+        (status = 200, description = "The public profile", body = PublicProfile),
+        UserErrors,
+    ),
+)]
+pub async fn user_by_username(
+    state: State<Arc<AppState>>,
+    Path(username): Path<String>,
+) -> Result<Json<PublicProfile>, Error> {
+    let user = resolve(&state.db, &username).await?;
+
+    Ok(Json(shown(&state.db, user).await?.into()))
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct UserLookup {
+    ids: Vec<i32>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct UserLookupResult {
+    users: Vec<PublicProfile>,
+    /// Requested ids that belong to no user, or to one hidden for being suspended or
+    /// scheduled for deletion.
+    missing: Vec<i32>,
+}
+
+errors!(LookupUsersErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    BadRequest,
+    RateLimited,
+    InternalServerError,
+);
+
+/// Resolves up to `accounts.lookup_limit` user ids to public profiles at once.
+#[utoipa::path(
+    post,
+    path = "/v0/users/lookup",
+    tag = "profile",
+    request_body = UserLookup,
+    security(("bearer" = []), ("oauth2" = ["users.read"])),
+    responses(
+        (status = 200, description = "The profiles found", body = UserLookupResult),
+        LookupUsersErrors,
+    ),
+)]
+pub async fn lookup_users(
+    state: State<Arc<AppState>>,
+    _claims: Claims<{ scopes(&[Scope::UsersRead]) }>,
+    Json(params): Json<UserLookup>,
+) -> Result<Json<UserLookupResult>, Error> {
+    let mut ids = params.ids;
+    ids.sort_unstable();
+    ids.dedup();
+    if ids.len() > state.config.accounts.lookup_limit {
+        return Err(Error::BadRequest);
+    }
+
+    let mut users = user::Entity::find()
+        .filter(user::Column::Id.is_in(ids.clone()))
+        .filter(user::Column::DeletionScheduledAt.is_null())
+        .all(&state.db)
+        .await
+        .warn_err()?;
+    let found: Vec<i32> = users.iter().map(|user| user.id).collect();
+    let suspended = suspension::suspended_among(&state.db, &found).await?;
+    users.retain(|user| !suspended.contains(&user.id));
+
+    let missing = ids
+        .into_iter()
+        .filter(|id| !users.iter().any(|user| user.id == *id))
+        .collect();
+
+    Ok(Json(UserLookupResult {
+        users: users.into_iter().map(PublicProfile::from).collect(),
+        missing,
     }))
 }
 
+errors!(EditErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    InvalidFields,
+    NotFound,
+    RateLimited,
+    InternalServerError,
+);
+
+/// Edits the profile of the current user and who may see its extended fields.
+#[utoipa::path(
+    put,
+    path = "/v0/me/edit",
+    tag = "profile",
+    request_body = ProfileEdit,
+    security(("bearer" = []), ("oauth2" = ["profile.write"])),
+    responses(
+        (status = 200, description = "The profile is saved"),
+        EditErrors,
+    ),
+)]
 pub async fn edit(
     state: State<Arc<AppState>>,
+    client: ClientInfo,
     claims: Claims<{ scopes(&[Scope::ProfileWrite]) }>,
     Json(params): Json<ProfileEdit>,
 ) -> Result<(), Error> {
-    params.validate().map_err(|_e| Error::BadRequest)?;
+    params.validate()?;
 
     let user = user::Entity::find_by_id(claims.uid)
         .one(&state.db)
         .await
         .warn_err()?
         .ok_or(Error::NotFound)?;
+    let mut visibility = FieldVisibility::of(&user);
     let mut user: user::ActiveModel = user.into();
+    let mut changed = Vec::new();
 
     if let Some(changed_name) = params.name {
         user.name = Set(changed_name);
+        changed.push("name");
+    }
+    if let Some(bio) = params.bio {
+        user.bio = Set(bio);
+        changed.push("bio");
+    }
+    if let Some(website) = params.website {
+        user.website = Set(website);
+        changed.push("website");
+    }
+    if let Some(locale) = params.locale {
+        user.locale = Set(locale);
+        changed.push("locale");
+    }
+    if let Some(timezone) = params.timezone {
+        user.timezone = Set(timezone);
+        changed.push("timezone");
+    }
+    if let Some(pronouns) = params.pronouns {
+        user.pronouns = Set(pronouns);
+        changed.push("pronouns");
+    }
+    if let Some(birthday) = params.birthday {
+        user.birthday = Set(birthday);
+        changed.push("birthday");
+    }
+    if let Some(edit) = params.visibility {
+        let fields = [
+            (&mut visibility.bio, edit.bio),
+            (&mut visibility.website, edit.website),
+            (&mut visibility.locale, edit.locale),
+            (&mut visibility.timezone, edit.timezone),
+            (&mut visibility.pronouns, edit.pronouns),
+            (&mut visibility.birthday, edit.birthday),
+        ];
+        for (current, new) in fields {
+            if let Some(new) = new {
+                *current = new;
+            }
+        }
+        user.profile_visibility = Set(json!(visibility));
+        changed.push("visibility");
     }
 
     user.update(&state.db).await.warn_err()?;
 
+    AuditEvent::new(EventType::ProfileEdited)
+        .user(claims.uid)
+        .client(&client)
+        .details(json!({ "fields": changed }))
+        .record(&state.db)
+        .await;
+
+    Ok(())
+}
+
+/// Tells a field set to `null` (`Some(None)`, clearing it) apart from a missing one.
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn validate_locale(locale: &str) -> Result<(), ValidationError> {
+    let mut parts = locale.split('-');
+    let language = parts.next().unwrap_or_default();
+    if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_lowercase()) {
+        return Err(ValidationError::new("locale"));
+    }
+    if !parts.all(|p| (2..=8).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphanumeric())) {
+        return Err(ValidationError::new("locale"));
+    }
+    Ok(())
+}
+
+fn validate_timezone(timezone: &str) -> Result<(), ValidationError> {
+    timezone
+        .parse::<Tz>()
+        .map(|_| ())
+        .map_err(|_| ValidationError::new("timezone"))
+}
+
+fn validate_birthday(birthday: &NaiveDate) -> Result<(), ValidationError> {
+    let min = NaiveDate::from_ymd_opt(1900, 1, 1).unwrap();
+    if *birthday < min || *birthday > Utc::now().date_naive() {
+        return Err(ValidationError::new("birthday"));
+    }
     Ok(())
 }
 
-#[derive(Serialize, Debug, Validate, Deserialize)]
+/// Fields left out are kept, fields set to `null` are cleared.
+#[derive(Debug, Validate, Deserialize, ToSchema)]
 pub struct ProfileEdit {
     #[validate(length(min = 3, max = 25))]
     name: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(length(max = 500))]
+    bio: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(url, length(max = 200))]
+    website: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(custom(function = "validate_locale"))]
+    locale: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(custom(function = "validate_timezone"))]
+    timezone: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(length(min = 1, max = 32))]
+    pronouns: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(custom(function = "validate_birthday"))]
+    birthday: Option<Option<NaiveDate>>,
+    visibility: Option<VisibilityEdit>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VisibilityEdit {
+    bio: Option<Visibility>,
+    website: Option<Visibility>,
+    locale: Option<Visibility>,
+    timezone: Option<Visibility>,
+    pronouns: Option<Visibility>,
+    birthday: Option<Visibility>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, ToSchema, Debug)]
 pub struct MyProfile {
     email: String,
     name: String,
-    id: u32,
+    username: String,
+    id: i32,
     created_at: NaiveDateTime,
     updated_at: NaiveDateTime,
-    avatar: Option<String>,
+    avatar: Avatar,
+    deletion_scheduled_at: Option<NaiveDateTime>,
+    #[serde(flatten)]
+    details: ProfileDetails,
+    /// Only shown to the user themselves.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    visibility: Option<FieldVisibility>,
+}
+
+impl MyProfile {
+    pub fn new(user: user::Model, viewer: Viewer) -> Self {
+        MyProfile {
+            details: ProfileDetails::new(&user, viewer),
+            visibility: (viewer == Viewer::Owner).then(|| FieldVisibility::of(&user)),
+            avatar: Avatar::new(user.avatar.as_deref(), &user.email),
+            email: user.email,
+            name: user.name,
+            username: user.username,
+            id: user.id,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            deletion_scheduled_at: user.deletion_scheduled_at,
+        }
+    }
+}
+
+impl From<user::Model> for MyProfile {
+    fn from(user: user::Model) -> Self {
+        MyProfile::new(user, Viewer::Owner)
+    }
+}
+
+/// What anyone may see about a user.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct PublicProfile {
+    id: i32,
+    username: String,
+    name: String,
+    avatar: Avatar,
+    #[serde(flatten)]
+    details: ProfileDetails,
+}
+
+impl From<user::Model> for PublicProfile {
+    fn from(user: user::Model) -> Self {
+        PublicProfile {
+            details: ProfileDetails::new(&user, Viewer::Public),
+            avatar: Avatar::public(user.avatar.as_deref(), user.id),
+            id: user.id,
+            username: user.username,
+            name: user.name,
+        }
+    }
 }
@@ -1,33 +1,33 @@
 use crate::data::error::Error;
+use crate::data::session::ClientInfo;
+use crate::entity::audit_event::EventType;
 use crate::entity::user;
+use crate::handler::openapi::errors;
+use crate::store::{generate_refresh_token, PendingRegistration};
+use crate::utils::audit::AuditEvent;
 use crate::utils::captcha::{verify_captcha, Captcha};
+use crate::utils::db::StanderizeError;
 use crate::utils::encryption::salt_password;
-use crate::utils::redis::{generate_refresh_token, get_connection};
+use crate::utils::metrics::{REGISTRATIONS, VERIFICATIONS};
+use crate::utils::username::{
+    availability, derive_username, normalize, validate_username, Availability,
+};
 use crate::AppState;
 use askama::Template;
 use axum::extract::{Query, State};
-use axum::http::HeaderMap;
 use axum::response::IntoResponse;
 use axum::Json;
-use lazy_static::lazy_static;
-use lettre::message::{header, Mailbox, SinglePart};
+use lettre::message::{header, SinglePart};
 use lettre::Message;
 use rand::distributions::Alphanumeric;
 use rand::Rng;
-use sea_orm::{ActiveValue, ColumnTrait, EntityTrait, QueryFilter};
-use serde::Deserialize;
-use std::env;
+use sea_orm::{ActiveValue, ColumnTrait, EntityTrait, QueryFilter, SqlErr};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::sync::Arc;
-use redis::Commands;
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
-use crate::utils::email::send;
-
-lazy_static! {
-    static ref FROM: Mailbox = env::var("FROM_MAILBOX")
-        .expect("FROM_MAILBOX must be set")
-        .parse()
-        .expect("parse from mailbox failed");
-}
 
 #[derive(Template)]
 #[template(path = "email_verification.html")]
@@ -35,139 +35,367 @@ struct EmailVerificationTemplate<'a> {
     verification_link: &'a str,
 }
 
+errors!(RegisterErrors:
+    InvalidFields,
+    MissingCaptchaToken,
+    InvalidCaptcha,
+    TimeOutOrDuplicateCaptcha,
+    BadRequest,
+    RegisteredEmail,
+    UsernameTaken,
+    RateLimited,
+    InternalServerError,
+);
+
+/// Starts a registration and mails a link that completes it.
+#[utoipa::path(
+    post,
+    path = "/v0/register",
+    tag = "registration",
+    request_body = RegisterPayload,
+    responses(
+        (status = 200, description = "The verification email is sent"),
+        RegisterErrors,
+    ),
+)]
 pub async fn register(
     state: State<Arc<AppState>>,
-    headers: HeaderMap,
+    client: ClientInfo,
     Json(payload): Json<RegisterPayload>,
 ) -> Result<(), impl IntoResponse> {
-    payload.validate().map_err(|_e| Error::BadRequest)?;
+    payload.validate()?;
 
-    let remote_ip = headers
-        .get("x-forwarded-for")
-        .and_then(|value| value.to_str().ok());
-
-    verify_captcha(payload.captcha, remote_ip).await?;
+    verify_captcha(&state.config.captcha, payload.captcha, client.ip.as_deref()).await?;
 
     if user::Entity::find()
         .filter(user::Column::Email.eq(&payload.email))
         .one(&state.db)
         .await
-        .map_err(map_database_error)?
+        .warn_err()?
         .is_some()
     {
         return Err(Error::RegisteredEmail);
     }
 
-    let email = Message::builder()
-        .subject("验证你的电子邮件")
-        .from(FROM.clone())
-        .to(payload.email.parse().map_err(|e| {
-            tracing::debug!("email illegal: {}", e);
-            Error::BadRequest
-        })?);
+    let store = state.store.as_ref();
+    let username = match payload.username.as_deref().map(normalize) {
+        Some(username) => {
+            if availability(&state.db, store, &username, None, Some(&payload.email)).await?
+                != Availability::Available
+            {
+                return Err(Error::UsernameTaken);
+            }
+            username
+        }
+        None => derive_username(&state.db, store, &payload.email).await?,
+    };
+
+    check_resend_cooldown(&state, &payload.email).await?;
+
+    let registration = PendingRegistration {
+        email: payload.email.clone(),
+        hashed_password: payload.hashed_password.clone(),
+        username,
+        display_name: payload.display_name.clone(),
+    };
+    let token = generate_refresh_token();
+    let ttl = Duration::from_secs(state.config.tokens.verification_ttl_seconds);
+    store.store_verification(&token, &registration, ttl).await?;
+
+    send_verification_email(&state, &payload.email, &token).await?;
+    REGISTRATIONS.inc();
+
+    AuditEvent::new(EventType::RegistrationRequested)
+        .client(&client)
+        .details(json!({ "email": payload.email }))
+        .record(&state.db)
+        .await;
+
+    Ok(())
+}
+
+errors!(ResendErrors:
+    InvalidFields,
+    MissingCaptchaToken,
+    InvalidCaptcha,
+    TimeOutOrDuplicateCaptcha,
+    BadRequest,
+    NotFound,
+    RateLimited,
+    InternalServerError,
+);
+
+/// Sends a new verification email for a pending registration, invalidating the
+/// previous link.
+#[utoipa::path(
+    post,
+    path = "/v0/register/resend",
+    tag = "registration",
+    request_body = ResendPayload,
+    responses(
+        (status = 200, description = "The verification email is sent"),
+        ResendErrors,
+    ),
+)]
+pub async fn resend(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    Json(payload): Json<ResendPayload>,
+) -> Result<(), Error> {
+    payload.validate()?;
+
+    verify_captcha(&state.config.captcha, payload.captcha, client.ip.as_deref()).await?;
+
+    let store = state.store.as_ref();
+    let previous = store
+        .pending_verification(&payload.email)
+        .await?
+        .ok_or(Error::NotFound)?;
+    let registration = store
+        .verification(&previous)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    check_resend_cooldown(&state, &payload.email).await?;
 
-    let mut conn = get_connection(&state.redis)?;
     let token = generate_refresh_token();
+    let ttl = Duration::from_secs(state.config.tokens.verification_ttl_seconds);
+    store.store_verification(&token, &registration, ttl).await?;
+
+    send_verification_email(&state, &payload.email, &token).await?;
+
+    AuditEvent::new(EventType::VerificationResent)
+        .client(&client)
+        .details(json!({ "email": payload.email }))
+        .record(&state.db)
+        .await;
+
+    Ok(())
+}
+
+errors!(StatusErrors: RateLimited, InternalServerError);
+
+/// Tells whether an email belongs to an account, is awaiting verification, or is unknown.
+#[utoipa::path(
+    get,
+    path = "/v0/register/status",
+    tag = "registration",
+    params(EmailQuery),
+    responses(
+        (status = 200, description = "The status of the email", body = RegistrationStatusResponse),
+        StatusErrors,
+    ),
+)]
+pub async fn status(
+    state: State<Arc<AppState>>,
+    Query(params): Query<EmailQuery>,
+) -> Result<Json<RegistrationStatusResponse>, Error> {
+    let registered = user::Entity::find()
+        .filter(user::Column::Email.eq(&params.email))
+        .one(&state.db)
+        .await
+        .warn_err()?
+        .is_some();
+
+    let status = if registered {
+        RegistrationStatus::Registered
+    } else {
+        let pending = state.store.pending_verification(&params.email).await?;
+
+        if pending.is_some() {
+            RegistrationStatus::Pending
+        } else {
+            RegistrationStatus::Unknown
+        }
+    };
+
+    Ok(Json(RegistrationStatusResponse { status }))
+}
+
+/// Allows one verification email per address every `tokens.verification_resend_cooldown`.
+async fn check_resend_cooldown(state: &AppState, email: &str) -> Result<(), Error> {
+    let key = format!("email_verify_cooldown:{}", email);
+    let cooldown = Duration::from_secs(state.config.tokens.verification_resend_cooldown_seconds);
 
-    let key = format!("email_verify:{}", &token);
+    if !state.store.set_nx(&key, "1", cooldown).await? {
+        let ttl = state.store.ttl(&key).await?.unwrap_or_default();
+        return Err(Error::RateLimited {
+            retry_after: ttl.as_secs().max(1),
+        });
+    }
 
-    let _: () = redis::pipe()
-        .atomic()
-        .hset_multiple(
-            &key,
-            &[
-                ("email", &payload.email),
-                ("hashed_password", &payload.hashed_password),
-            ],
+    Ok(())
+}
+
+async fn send_verification_email(state: &AppState, email: &str, token: &str) -> Result<(), Error> {
+    let message = Message::builder()
+        .subject("验证你的电子邮件")
+        .from(state.mailer.from.clone())
+        .to(email.parse().map_err(|e| {
+            tracing::debug!("email illegal: {}", e);
+            Error::BadRequest
+        })?)
+        .singlepart(
+            SinglePart::builder()
+                .header(header::ContentType::TEXT_HTML)
+                .body(
+                    EmailVerificationTemplate {
+                        verification_link: &state
+                            .config
+                            .server
+                            .link(&format!("/verify?code={}", token)),
+                    }
+                    .render()
+                    .map_err(|e| {
+                        tracing::warn!("email template render failed: {}", e);
+                        Error::InternalServerError
+                    })?,
+                ),
         )
-        .ignore()
-        .expire(&key, 24 * 60 * 60)
-        .ignore()
-        .query(&mut conn)
         .map_err(|e| {
-            tracing::warn!("redis error when record email verification token: {}", e);
+            tracing::warn!("failed to build email: {}", e);
             Error::InternalServerError
         })?;
 
-    let email = email.singlepart(
-        SinglePart::builder()
-            .header(header::ContentType::TEXT_HTML)
-            .body(
-                EmailVerificationTemplate {
-                    verification_link: &format!("https://nuke.websxz.org/verify?code={}", &token),
-                }
-                .render()
-                .map_err(|e| {
-                    tracing::warn!("email template render failed: {}", e);
-                    Error::InternalServerError
-                })?,
-            ),
-    ).map_err(|e| {
-        tracing::warn!("failed to build email: {}",e);
-        Error::InternalServerError
-    })?;
-
-    let _ = send(&email)?;
-
-    Ok(())
+    state.mailer.send(message).await
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct TokenQuery {
+    /// The token of the link in the verification email.
     token: String,
 }
 
+errors!(VerifyErrors: NotFound, RegisteredEmail, UsernameTaken, InternalServerError);
 
+/// Completes the registration a verification link was sent for.
+#[utoipa::path(
+    get,
+    path = "/v0/verify",
+    tag = "registration",
+    params(TokenQuery),
+    responses(
+        (status = 200, description = "The account is created"),
+        VerifyErrors,
+    ),
+)]
 pub async fn verify(
     state: State<Arc<AppState>>,
+    client: ClientInfo,
     Query(params): Query<TokenQuery>,
 ) -> Result<(), impl IntoResponse> {
-    let TokenQuery { token } = params;
-
-    let mut conn = get_connection(&state.redis)?;
-    let key = format!("email_verify:{}", &token);
-    let v: (Option<String>, Option<String>) = conn.hget(&key, &["email", "hashed_password"]).map_err(map_database_error)?;
-
-    if let (Some(email), Some(hashed_password)) = v {
-        let _: () = conn.del(&key).map_err(map_database_error)?;
-
-        let salt: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(16)
-            .map(char::from)
-            .collect();
-
-        let salted_password = salt_password(&hashed_password, &salt);
-        let name = email[..email.find('@').unwrap()].to_string();
-        user::Entity::insert(user::ActiveModel {
-            id: ActiveValue::NotSet,
-            name: ActiveValue::Set(name),
-            email: ActiveValue::Set(email.clone()),
-            avatar: ActiveValue::Set(None),
-            salted_password: ActiveValue::Set(salted_password),
-            salt: ActiveValue::Set(salt),
-            created_at: ActiveValue::NotSet,
-            updated_at: ActiveValue::NotSet,
-        })
-            .exec(&state.db)
-            .await
-            .map_err(map_database_error)?;
-
-        return Ok(());
-    }
+    let uid = complete_registration(&state, &params.token)
+        .await
+        .inspect_err(|e| {
+            if let Error::NotFound = e {
+                VERIFICATIONS.with_label_values(&["invalid"]).inc();
+            }
+        })?;
+    VERIFICATIONS.with_label_values(&["verified"]).inc();
+
+    AuditEvent::new(EventType::Registered)
+        .user(uid)
+        .client(&client)
+        .record(&state.db)
+        .await;
 
-    Err(Error::NotFound)
+    Ok::<_, Error>(())
 }
 
-fn map_database_error(e: impl std::error::Error) -> Error {
-    tracing::warn!("database error: {}", e);
-    Error::InternalServerError
+/// Creates the account a verification token was issued for and returns its id.
+pub(crate) async fn complete_registration(state: &AppState, token: &str) -> Result<i32, Error> {
+    let PendingRegistration {
+        email,
+        hashed_password,
+        username,
+        display_name,
+    } = state
+        .store
+        .consume_verification(token)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let salt: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+
+    let salted_password = salt_password(&hashed_password, &salt);
+    let name = display_name.unwrap_or_else(|| username.clone());
+    let inserted = user::Entity::insert(user::ActiveModel {
+        id: ActiveValue::NotSet,
+        name: ActiveValue::Set(name),
+        username: ActiveValue::Set(username.clone()),
+        email: ActiveValue::Set(email.clone()),
+        avatar: ActiveValue::Set(None),
+        bio: ActiveValue::Set(None),
+        website: ActiveValue::Set(None),
+        locale: ActiveValue::Set(None),
+        timezone: ActiveValue::Set(None),
+        pronouns: ActiveValue::Set(None),
+        birthday: ActiveValue::Set(None),
+        profile_visibility: ActiveValue::Set(serde_json::json!({})),
+        role: ActiveValue::Set(user::Role::User),
+        salted_password: ActiveValue::Set(salted_password),
+        salt: ActiveValue::Set(salt),
+        deletion_scheduled_at: ActiveValue::Set(None),
+        created_at: ActiveValue::NotSet,
+        updated_at: ActiveValue::NotSet,
+    })
+    .exec(&state.db)
+    .await
+    .or_else(|e| match e.sql_err() {
+        Some(SqlErr::UniqueConstraintViolation(message)) if message.contains("email") => {
+            Err(Error::RegisteredEmail)
+        }
+        Some(SqlErr::UniqueConstraintViolation(_)) => Err(Error::UsernameTaken),
+        _ => Err(e).warn_err(),
+    })?;
+
+    state.store.release_username_claim(&username).await?;
+
+    Ok(inserted.last_insert_id)
 }
 
-#[derive(Deserialize, Debug, Validate)]
+#[derive(Deserialize, ToSchema, Debug, Validate)]
 pub struct RegisterPayload {
     #[validate(email)]
     email: String,
+    /// Derived from the email when absent.
+    #[validate(custom(function = "validate_username"))]
+    username: Option<String>,
+    #[validate(length(min = 3, max = 25))]
+    display_name: Option<String>,
     hashed_password: String,
     captcha: Captcha,
 }
+
+#[derive(Deserialize, ToSchema, Debug, Validate)]
+pub struct ResendPayload {
+    #[validate(email)]
+    email: String,
+    captcha: Captcha,
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct EmailQuery {
+    email: String,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub enum RegistrationStatus {
+    #[serde(rename = "registered")]
+    Registered,
+    #[serde(rename = "pending")]
+    Pending,
+    #[serde(rename = "unknown")]
+    Unknown,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct RegistrationStatusResponse {
+    status: RegistrationStatus,
+}
@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use crate::{
+    data::{
+        credential::{scopes, Claims, Scope},
+        error::Error,
+        session::{ClientInfo, Session},
+    },
+    entity::audit_event::EventType,
+    handler::openapi::errors,
+    store::TokenPurpose,
+    utils::audit::AuditEvent,
+    AppState,
+};
+use serde::Deserialize;
+use serde_json::json;
+use utoipa::ToSchema;
+
+errors!(SessionsErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    InternalServerError,
+);
+
+/// The sessions the current user is signed in with.
+#[utoipa::path(
+    get,
+    path = "/v0/me/sessions",
+    tag = "account",
+    security(("bearer" = []), ("oauth2" = ["sessions.read"])),
+    responses(
+        (status = 200, description = "The sessions, with the current one marked", body = [Session]),
+        SessionsErrors,
+    ),
+)]
+pub async fn sessions(
+    state: State<Arc<AppState>>,
+    claims: Claims<{ scopes(&[Scope::SessionsRead]) }>,
+) -> Result<Json<Vec<Session>>, Error> {
+    Ok(Json(
+        state
+            .store
+            .list_sessions(claims.uid, claims.sid.as_deref())
+            .await?,
+    ))
+}
+
+errors!(DeleteSessionErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    NotFound,
+    InternalServerError,
+);
+
+/// Signs out a single session of the current user.
+#[utoipa::path(
+    delete,
+    path = "/v0/me/sessions/{id}",
+    tag = "account",
+    params(("id" = String, Path, description = "The id of the session")),
+    security(("bearer" = []), ("oauth2" = ["sessions.write"])),
+    responses(
+        (status = 200, description = "The session is revoked"),
+        DeleteSessionErrors,
+    ),
+)]
+pub async fn delete_session(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    claims: Claims<{ scopes(&[Scope::SessionsWrite]) }>,
+    Path(id): Path<String>,
+) -> Result<(), Error> {
+    if !state.store.revoke_session(claims.uid, &id).await? {
+        return Err(Error::NotFound);
+    }
+
+    AuditEvent::new(EventType::SessionRevoked)
+        .user(claims.uid)
+        .client(&client)
+        .details(json!({ "session": id }))
+        .record(&state.db)
+        .await;
+
+    Ok(())
+}
+
+/// Signs the user out everywhere, including the session making the request.
+#[utoipa::path(
+    delete,
+    path = "/v0/me/sessions",
+    tag = "account",
+    security(("bearer" = []), ("oauth2" = ["sessions.write"])),
+    responses(
+        (status = 200, description = "Every session is revoked"),
+        SessionsErrors,
+    ),
+)]
+pub async fn delete_sessions(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    claims: Claims<{ scopes(&[Scope::SessionsWrite]) }>,
+) -> Result<(), Error> {
+    state.store.revoke_all_sessions(claims.uid).await?;
+
+    AuditEvent::new(EventType::AllSessionsRevoked)
+        .user(claims.uid)
+        .client(&client)
+        .record(&state.db)
+        .await;
+
+    Ok(())
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct RevokeLinkBody {
+    token: String,
+}
+
+errors!(RevokeByLinkErrors: NotFound, InternalServerError);
+
+/// Revokes the session a new sign-in alert was sent for, without requiring a login.
+#[utoipa::path(
+    post,
+    path = "/v0/sessions/revoke",
+    tag = "account",
+    request_body = RevokeLinkBody,
+    responses(
+        (status = 200, description = "The session is revoked"),
+        RevokeByLinkErrors,
+    ),
+)]
+pub async fn revoke_by_link(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    Json(data): Json<RevokeLinkBody>,
+) -> Result<(), Error> {
+    let target = state
+        .store
+        .consume_token(TokenPurpose::SessionRevoke, &data.token)
+        .await?;
+
+    let (uid, session) = target
+        .as_deref()
+        .and_then(|target| target.split_once(':'))
+        .and_then(|(uid, session)| Some((uid.parse::<i32>().ok()?, session)))
+        .ok_or(Error::NotFound)?;
+
+    state.store.revoke_session(uid, session).await?;
+
+    AuditEvent::new(EventType::SessionRevoked)
+        .subject(uid)
+        .client(&client)
+        .details(json!({ "session": session, "via": "email" }))
+        .record(&state.db)
+        .await;
+
+    Ok(())
+}
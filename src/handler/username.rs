@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use chrono::Duration;
+use sea_orm::{EntityTrait, TransactionTrait};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    data::{
+        credential::{scopes, Claims, Scope},
+        error::Error,
+        session::ClientInfo,
+    },
+    entity::{audit_event::EventType, user},
+    handler::openapi::errors,
+    utils::{
+        audit::AuditEvent,
+        db::StanderizeError,
+        username::{availability, normalize, rename, Availability},
+    },
+    AppState,
+};
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct UsernameQuery {
+    username: String,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct UsernameAvailability {
+    username: String,
+    availability: Availability,
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct UsernameChange {
+    username: String,
+}
+
+errors!(AvailableErrors: RateLimited, InternalServerError);
+
+/// Whether a handle can be registered, and how it would be stored.
+#[utoipa::path(
+    get,
+    path = "/v0/username/available",
+    tag = "registration",
+    params(UsernameQuery),
+    responses(
+        (status = 200, description = "The availability of the handle", body = UsernameAvailability),
+        AvailableErrors,
+    ),
+)]
+pub async fn available(
+    state: State<Arc<AppState>>,
+    Query(query): Query<UsernameQuery>,
+) -> Result<Json<UsernameAvailability>, Error> {
+    Ok(Json(UsernameAvailability {
+        availability: availability(&state.db, state.store.as_ref(), &query.username, None, None)
+            .await?,
+        username: normalize(&query.username),
+    }))
+}
+
+errors!(ChangeUsernameErrors:
+    InvalidToken,
+    ExpiredToken,
+    MissingScope,
+    AccountSuspended,
+    NotFound,
+    BadRequest,
+    UsernameTaken,
+    RateLimited,
+    InternalServerError,
+);
+
+/// Changes the current user's handle, at most once per cooldown period.
+///
+/// The old handle keeps resolving to the user for a while and cannot be claimed by
+/// anyone else in the meantime.
+#[utoipa::path(
+    put,
+    path = "/v0/me/username",
+    tag = "profile",
+    request_body = UsernameChange,
+    security(("bearer" = []), ("oauth2" = ["profile.write"])),
+    responses(
+        (status = 200, description = "The handle is changed"),
+        ChangeUsernameErrors,
+    ),
+)]
+pub async fn change_username(
+    state: State<Arc<AppState>>,
+    client: ClientInfo,
+    claims: Claims<{ scopes(&[Scope::ProfileWrite]) }>,
+    Json(params): Json<UsernameChange>,
+) -> Result<(), Error> {
+    let username = normalize(&params.username);
+    let store = state.store.as_ref();
+    let config = &state.config.accounts;
+
+    let user = user::Entity::find_by_id(claims.uid)
+        .one(&state.db)
+        .await
+        .warn_err()?
+        .ok_or(Error::NotFound)?;
+    if user.username == username {
+        return Ok(());
+    }
+
+    match availability(&state.db, store, &username, Some(claims.uid), None).await? {
+        Availability::Available => {}
+        Availability::Invalid => return Err(Error::BadRequest),
+        Availability::Reserved | Availability::Taken => return Err(Error::UsernameTaken),
+    }
+
+    // The cooldown is claimed up front so that concurrent changes cannot both get in.
+    let cooldown_key = format!("username_cooldown:{}", claims.uid);
+    let cooldown = Duration::days(config.username_change_cooldown_days)
+        .to_std()
+        .unwrap_or_default();
+    if !store.set_nx(&cooldown_key, "1", cooldown).await? {
+        let ttl = store.ttl(&cooldown_key).await?.unwrap_or_default();
+        return Err(Error::RateLimited {
+            retry_after: ttl.as_secs().max(1),
+        });
+    }
+
+    let renamed = async {
+        let txn = state.db.begin().await.warn_err()?;
+        rename(&txn, &user, &username, config.username_redirect_days).await?;
+        txn.commit().await.warn_err()
+    };
+    if let Err(e) = renamed.await {
+        store.delete(&cooldown_key).await?;
+        return Err(e);
+    }
+
+    AuditEvent::new(EventType::UsernameChanged)
+        .user(claims.uid)
+        .client(&client)
+        .details(json!({ "from": user.username, "to": username }))
+        .record(&state.db)
+        .await;
+
+    Ok(())
+}
@@ -1,12 +1,33 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
 use sea_orm::DatabaseConnection;
 
+use crate::config::Config;
+use crate::data::credential::Keys;
+use crate::middleware::requests::RequestStats;
+use crate::store::EphemeralStore;
+use crate::utils::email::Mailer;
+use crate::utils::geoip::GeoIp;
+use crate::utils::storage::ObjectStorage;
+
+pub mod config;
 pub mod data;
 pub mod entity;
 pub mod handler;
 pub mod middleware;
+pub mod store;
 pub mod utils;
 
 pub struct AppState {
+    pub config: Arc<Config>,
     pub db: DatabaseConnection,
-    pub redis: redis::Client,
+    pub store: Arc<dyn EphemeralStore>,
+    pub storage: Arc<dyn ObjectStorage>,
+    pub keys: Keys,
+    pub mailer: Mailer,
+    pub geoip: GeoIp,
+    /// Set once shutdown has begun, so that readiness fails while connections drain.
+    pub draining: AtomicBool,
+    pub requests: Arc<RequestStats>,
 }
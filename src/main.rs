@@ -1,50 +1,367 @@
-use axum::routing::{get, post, put};
+use axum::routing::{delete, get, post, put};
+use axum::extract::DefaultBodyLimit;
 use axum::Router;
 use dotenv::dotenv;
-use sea_orm::Database;
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{Database, DatabaseConnection};
 use websxz_accounts_backend::handler::oauth::{exchange_token, oauth};
-use websxz_accounts_backend::handler::profile::{edit, me};
+use websxz_accounts_backend::handler::profile::{
+    edit, lookup_users, me, user, user_by_username,
+};
 use std::env;
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tracing_subscriber::fmt;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
-use websxz_accounts_backend::handler::login::{login, refresh_token};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use opentelemetry_sdk::trace::TracerProvider;
+use websxz_accounts_backend::handler::login::{login, refresh_token, unlock};
 use websxz_accounts_backend::AppState;
-use websxz_accounts_backend::handler::register::{register, verify};
+use websxz_accounts_backend::handler::register::{register, resend, status, verify};
+use websxz_accounts_backend::handler::email_login::{login_email, verify_login_email};
+use websxz_accounts_backend::handler::session::{
+    delete_session, delete_sessions, revoke_by_link, sessions,
+};
+use websxz_accounts_backend::handler::audit::{activity, events};
+use websxz_accounts_backend::handler::account::delete_me;
+use websxz_accounts_backend::handler::admin::{
+    edit_user, force_verify, get_user, revoke_user_sessions, suspend, unsuspend, users,
+};
+use websxz_accounts_backend::handler::avatar::{delete_avatar, upload_avatar};
+use websxz_accounts_backend::handler::health::{healthz, readyz};
+use websxz_accounts_backend::handler::metrics::{admin_metrics, metrics};
+use websxz_accounts_backend::handler::openapi::openapi;
+use websxz_accounts_backend::handler::export::{download_export, export};
+use websxz_accounts_backend::handler::username::{available, change_username};
+use websxz_accounts_backend::utils::account::purge_deleted_accounts;
+use websxz_accounts_backend::utils::suspension::lift_expired_suspensions;
+use websxz_accounts_backend::utils::email::Mailer;
+use websxz_accounts_backend::utils::geoip::GeoIp;
+use websxz_accounts_backend::utils::storage;
+use websxz_accounts_backend::utils::telemetry;
+use tower_http::services::ServeDir;
+use websxz_accounts_backend::middleware::rate_limit::{Algorithm, KeyBy, RateLimitLayer};
+use websxz_accounts_backend::middleware::metrics::HttpMetricsLayer;
+use websxz_accounts_backend::middleware::problem::LocalizeErrorsLayer;
+use websxz_accounts_backend::middleware::request_id::RequestIdLayer;
+use websxz_accounts_backend::middleware::requests::{CountRequestsLayer, RequestStats};
+use websxz_accounts_backend::store;
+use websxz_accounts_backend::config::{AvatarStorage, Config};
+use websxz_accounts_backend::data::credential::Keys;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
-    tracing_subscriber::registry().with(fmt::layer()).init();
-    let db_url = env::var("DB_URL").expect("DB_URL must be set");
-    let redis_url = env::var("REDIS_URL").expect("REDIS_URL must be set");
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("migrate") {
+        tracing_subscriber::fmt().init();
+        let url = Config::database_url().unwrap_or_else(|e| {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        });
+        let db = Database::connect(&url)
+            .await
+            .expect("database connect failed");
+        migrate(&db, &args[1..]).await;
+        return;
+    }
 
-    let redis_client = redis::Client::open(redis_url).expect("failed to connect redis");
-    let db = Database::connect(db_url)
+    let config = Config::load().unwrap_or_else(|e| {
+        tracing_subscriber::fmt().init();
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    });
+    let tracer_provider = telemetry::init(&config.telemetry);
+    let mut db = Database::connect(&config.database.url)
         .await
         .expect("database connect failed");
+    telemetry::trace_queries(&mut db);
+
+    if config.server.auto_migrate {
+        Migrator::up(&db, None).await.expect("migration failed");
+    }
+
+    let geoip = GeoIp::open(config.geoip.db_path.as_deref()).unwrap_or_else(|e| {
+        tracing::error!("failed to open GeoIP database: {}", e);
+        std::process::exit(1);
+    });
+
+    let state = Arc::new(AppState {
+        db,
+        store: store::from_config(&config).await,
+        storage: storage::from_config(&config.avatars).unwrap_or_else(|e| {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }),
+        keys: Keys::new(config.auth.jwt_secret.as_bytes()),
+        mailer: Mailer::new(&config.smtp),
+        geoip,
+        config: Arc::new(config),
+        draining: AtomicBool::new(false),
+        requests: Arc::new(RequestStats::default()),
+    });
+
+    let strict = |name| {
+        RateLimitLayer::from_config(
+            name,
+            Algorithm::SlidingWindow {
+                limit: 10,
+                window: Duration::from_secs(10 * 60),
+            },
+            KeyBy::Ip,
+            state.clone(),
+        )
+    };
+    let moderate = |name, key_by| {
+        RateLimitLayer::from_config(
+            name,
+            Algorithm::TokenBucket {
+                capacity: 30,
+                refill_per_second: 0.5,
+            },
+            key_by,
+            state.clone(),
+        )
+    };
+
+    let admin = Router::new()
+        .route("/audit", get(events))
+        .route("/users", get(users))
+        .route("/users/:id", get(get_user).patch(edit_user))
+        .route("/users/:id/sessions", delete(revoke_user_sessions))
+        .route("/users/:id/suspension", put(suspend).delete(unsuspend))
+        .route("/registrations/verify", post(force_verify));
 
     let v0 = Router::new()
-        .route("/login", post(login))
-        .route("/refresh", get(refresh_token))
-        .route("/register", post(register))
-        .route("/verify", get(verify))
-        .route("/oauth", get(oauth))
-        .route("/oauth/token", get(exchange_token))
-        .route("/me", get(me))
-        .route("/me/edit", put(edit))
-        .with_state(Arc::new(AppState {
-            db,
-            redis: redis_client,
-        }));
-
-    let app = Router::new().nest("/v0", v0);
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:8080")
-        .await
-        .unwrap();
+        .route("/login", post(login).layer(strict("login")))
+        .route(
+            "/login/unlock",
+            post(unlock).layer(strict("login_unlock")),
+        )
+        .route(
+            "/login/email",
+            post(login_email).layer(strict("login_email")),
+        )
+        .route(
+            "/login/email/verify",
+            post(verify_login_email).layer(strict("login_email_verify")),
+        )
+        .route(
+            "/refresh",
+            get(refresh_token).layer(moderate("refresh", KeyBy::Ip)),
+        )
+        .route("/register", post(register).layer(strict("register")))
+        .route(
+            "/register/resend",
+            post(resend).layer(strict("register_resend")),
+        )
+        .route(
+            "/register/status",
+            get(status).layer(moderate("register_status", KeyBy::Ip)),
+        )
+        .route("/verify", get(verify).layer(moderate("verify", KeyBy::Ip)))
+        .route(
+            "/username/available",
+            get(available).layer(moderate("username_available", KeyBy::Ip)),
+        )
+        .route(
+            "/oauth",
+            get(oauth).layer(moderate("oauth", KeyBy::ClientId)),
+        )
+        .route(
+            "/oauth/token",
+            get(exchange_token).layer(moderate("oauth_token", KeyBy::Ip)),
+        )
+        .route("/me", get(me).delete(delete_me))
+        .route(
+            "/me/edit",
+            put(edit).layer(moderate("profile_edit", KeyBy::User)),
+        )
+        .route("/me/username", put(change_username))
+        .route(
+            "/me/avatar",
+            put(upload_avatar)
+                .delete(delete_avatar)
+                .route_layer(DefaultBodyLimit::max(state.config.avatars.max_bytes))
+                .layer(moderate("avatar", KeyBy::User)),
+        )
+        .route("/me/sessions", get(sessions).delete(delete_sessions))
+        .route("/me/sessions/:id", delete(delete_session))
+        .route(
+            "/sessions/revoke",
+            post(revoke_by_link).layer(moderate("sessions_revoke", KeyBy::Ip)),
+        )
+        .route("/me/activity", get(activity))
+        .route(
+            "/me/export",
+            post(export).layer(moderate("export", KeyBy::User)),
+        )
+        .route("/me/export/:token", get(download_export))
+        .route(
+            "/users/:id",
+            get(user).layer(moderate("users", KeyBy::Ip)),
+        )
+        .route(
+            "/users/by-username/:username",
+            get(user_by_username).layer(moderate("users", KeyBy::Ip)),
+        )
+        .route(
+            "/users/lookup",
+            post(lookup_users).layer(moderate("users_lookup", KeyBy::User)),
+        )
+        .nest("/admin", admin)
+        .with_state(state.clone());
+
+    // Next to the API, the counters are only shown to admins.
+    let metrics_app = match state.config.server.metrics_listen {
+        Some(_) => Router::new().route("/metrics", get(metrics)),
+        None => Router::new().route("/metrics", get(admin_metrics)),
+    }
+    .with_state(state.clone());
+    let mut app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/openapi.json", get(openapi))
+        .nest("/v0", v0);
+    if state.config.avatars.storage == AvatarStorage::Local {
+        app = app.nest_service("/avatars", ServeDir::new(&state.config.avatars.local_dir));
+    }
+    let mut app = app.layer(HttpMetricsLayer).with_state(state.clone());
+    let mut tasks = vec![
+        tokio::spawn(purge_deleted_accounts(state.clone())),
+        tokio::spawn(lift_expired_suspensions(state.clone())),
+    ];
+    match state.config.server.metrics_listen {
+        Some(addr) => {
+            let listener = bind(addr).await;
+            tasks.push(tokio::spawn(async move {
+                if let Err(e) = axum::serve(listener, metrics_app).await {
+                    tracing::error!("metrics server failed: {}", e);
+                }
+            }));
+        }
+        None => app = app.merge(metrics_app),
+    }
+    #[cfg(feature = "swagger-ui")]
+    {
+        use utoipa_swagger_ui::{Config as SwaggerConfig, SwaggerUi};
+
+        app = app.merge(SwaggerUi::new("/docs").config(SwaggerConfig::from("/openapi.json")));
+    }
+    let app = app
+        .layer(LocalizeErrorsLayer)
+        .layer(CountRequestsLayer::new(state.requests.clone()))
+        .layer(RequestIdLayer);
+    let listener = bind(state.config.server.listen).await;
 
     tracing::info!("Server started.");
-    axum::serve(listener, app).await.unwrap();
+    let drained = Arc::new(Notify::new());
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(drain(state.clone(), drained.clone()))
+    .into_future();
+    let timeout = Duration::from_secs(state.config.server.shutdown_timeout_seconds);
+    tokio::select! {
+        result = server => result.unwrap(),
+        _ = async {
+            drained.notified().await;
+            tokio::time::sleep(timeout).await;
+        } => {
+            tracing::warn!(
+                "gave up on {} requests still in flight after {}s",
+                state.requests.in_flight(),
+                timeout.as_secs()
+            );
+        }
+    }
+
+    for task in tasks {
+        task.abort();
+        let _ = task.await;
+    }
+    tracing::info!("served {} requests", state.requests.total());
+    close(state, tracer_provider).await;
+}
+
+async fn bind(addr: SocketAddr) -> TcpListener {
+    TcpListener::bind(addr).await.unwrap_or_else(|e| {
+        tracing::error!("failed to listen on {}: {}", addr, e);
+        std::process::exit(1);
+    })
+}
+
+/// Resolves once a shutdown signal has arrived and the drain period is over, failing
+/// readiness meanwhile. The server then stops accepting connections and waits for the
+/// requests in flight.
+async fn drain(state: Arc<AppState>, drained: Arc<Notify>) {
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    let received = tokio::select! {
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    };
+
+    let drain = Duration::from_secs(state.config.server.drain_seconds);
+    tracing::info!(
+        "received {}, draining for {}s with {} requests in flight",
+        received,
+        drain.as_secs(),
+        state.requests.in_flight()
+    );
+    state.draining.store(true, Ordering::Relaxed);
+    tokio::time::sleep(drain).await;
+
+    tracing::info!(
+        "no longer accepting connections, waiting for {} requests in flight",
+        state.requests.in_flight()
+    );
+    drained.notify_one();
+}
+
+/// Closes the database pool, and the Redis connection and SMTP pool by dropping the last
+/// reference to them, then flushes the spans left to export.
+async fn close(state: Arc<AppState>, tracer_provider: TracerProvider) {
+    if let Err(e) = state.db.clone().close().await {
+        tracing::warn!("failed to close the database pool: {}", e);
+    }
+    if Arc::try_unwrap(state).is_err() {
+        tracing::warn!("connections still in use will be closed on exit");
+    }
+    // Flushing blocks until the exporter is done, which needs the runtime to make progress.
+    match tokio::task::spawn_blocking(move || tracer_provider.shutdown()).await {
+        Ok(Err(e)) => tracing::warn!("failed to flush spans: {}", e),
+        Err(e) => tracing::warn!("failed to flush spans: {}", e),
+        Ok(Ok(())) => {}
+    }
+
+    tracing::info!("shutdown complete");
+}
+
+/// Runs `migrate up`, `migrate down [steps]` (one step by default) or `migrate status`.
+async fn migrate(db: &DatabaseConnection, args: &[String]) {
+    let result = match args.first().map(String::as_str) {
+        Some("up") => Migrator::up(db, None).await,
+        Some("down") => {
+            let steps = args
+                .get(1)
+                .map(|steps| steps.parse().expect("steps must be a number"))
+                .unwrap_or(1);
+            Migrator::down(db, Some(steps)).await
+        }
+        Some("status") => Migrator::status(db).await,
+        _ => {
+            eprintln!("usage: migrate <up|down [steps]|status>");
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        tracing::error!("migration failed: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -1 +1,5 @@
-
+pub mod rate_limit;
+pub mod requests;
+pub mod metrics;
+pub mod problem;
+pub mod request_id;
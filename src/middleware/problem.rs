@@ -0,0 +1,69 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::http::{header, Request};
+use axum::response::{IntoResponse, Response};
+use tower::{Layer, Service};
+
+use crate::data::error::Error;
+use crate::data::locale::Locale;
+use crate::middleware::request_id::RequestId;
+
+/// Renders [`Error`] responses again in the language asked for by `Accept-Language`, with
+/// the id of the request.
+///
+/// Has to be inside [`RequestIdLayer`](crate::middleware::request_id::RequestIdLayer) to
+/// know the id.
+#[derive(Clone, Default)]
+pub struct LocalizeErrorsLayer;
+
+impl<S> Layer<S> for LocalizeErrorsLayer {
+    type Service = LocalizeErrors<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LocalizeErrors { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct LocalizeErrors<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for LocalizeErrors<S>
+where
+    S: Service<Request<Body>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let locale = Locale::negotiate(request.headers());
+        let request_id = request.extensions().get::<RequestId>().cloned();
+        let future = self.inner.call(request);
+
+        Box::pin(async move {
+            let mut response = future.await?;
+
+            if let Some(error) = response.extensions_mut().remove::<Error>() {
+                let request_id = request_id.as_ref().map(|id| id.0.as_str());
+                let (mut parts, _) = response.into_parts();
+                parts.headers.remove(header::CONTENT_LENGTH);
+                let problem = error.problem(locale, request_id).into_response();
+                let (problem, body) = problem.into_parts();
+                parts.headers.extend(problem.headers);
+                response = Response::from_parts(parts, body);
+            }
+
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,331 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderValue, Request};
+use axum::response::{IntoResponse, Response};
+use tower::{Layer, Service};
+
+use crate::data::credential::{decode_uid, Keys};
+use crate::data::error::Error;
+use crate::data::session::client_ip;
+use crate::AppState;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Algorithm {
+    /// Allows bursts of up to `capacity` requests, refilled at `refill_per_second`.
+    TokenBucket {
+        capacity: u32,
+        refill_per_second: f64,
+    },
+    /// Allows at most `limit` requests in any `window`.
+    SlidingWindow { limit: u32, window: Duration },
+}
+
+impl Algorithm {
+    /// Parses `token_bucket:<capacity>:<refill per second>` or
+    /// `sliding_window:<limit>:<window in seconds>`, all of which must be positive.
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        let positive = |n: &str| n.parse::<u32>().ok().filter(|n| *n > 0);
+
+        let mut parts = s.split(':');
+        let algorithm = match (parts.next()?, parts.next()?, parts.next()?) {
+            ("token_bucket", capacity, refill) => Algorithm::TokenBucket {
+                capacity: positive(capacity)?,
+                refill_per_second: refill
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|refill| refill.is_finite() && *refill > 0.0)?,
+            },
+            ("sliding_window", limit, window) => Algorithm::SlidingWindow {
+                limit: positive(limit)?,
+                window: Duration::from_secs(positive(window)?.into()),
+            },
+            _ => return None,
+        };
+
+        parts.next().is_none().then_some(algorithm)
+    }
+
+    fn limit(&self) -> u32 {
+        match self {
+            Algorithm::TokenBucket { capacity, .. } => *capacity,
+            Algorithm::SlidingWindow { limit, .. } => *limit,
+        }
+    }
+
+    fn policy(&self) -> String {
+        match self {
+            Algorithm::TokenBucket {
+                capacity,
+                refill_per_second,
+            } => format!(
+                "{};w={}",
+                capacity,
+                (*capacity as f64 / refill_per_second).ceil() as u64
+            ),
+            Algorithm::SlidingWindow { limit, window } => {
+                format!("{};w={}", limit, window.as_secs())
+            }
+        }
+    }
+}
+
+/// What requests are grouped by when counting them against a limit.
+#[derive(Debug, Clone, Copy)]
+pub enum KeyBy {
+    Ip,
+    /// The user id of the bearer token, falling back to the IP for anonymous requests.
+    User,
+    /// The `client_id` query parameter, falling back to the IP when absent.
+    ClientId,
+}
+
+impl KeyBy {
+    fn key(&self, request: &Request<Body>, keys: &Keys, trusted_proxies: usize) -> String {
+        let ip = || match client_ip(request.headers(), request.extensions(), trusted_proxies) {
+            Some(ip) => format!("ip:{}", ip),
+            None => "ip:unknown".to_string(),
+        };
+
+        match self {
+            KeyBy::Ip => ip(),
+            KeyBy::User => request
+                .headers()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .and_then(|token| decode_uid(keys, token))
+                .map(|uid| format!("user:{}", uid))
+                .unwrap_or_else(ip),
+            KeyBy::ClientId => request
+                .uri()
+                .query()
+                .and_then(|query| {
+                    query
+                        .split('&')
+                        .filter_map(|pair| pair.split_once('='))
+                        .find(|(k, _)| *k == "client_id")
+                        .map(|(_, v)| format!("client:{}", v))
+                })
+                .unwrap_or_else(ip),
+        }
+    }
+}
+
+/// The names of the rate limits wrapping the routes, which `rate_limits` in the config
+/// can override.
+pub const NAMES: &[&str] = &[
+    "avatar",
+    "export",
+    "login",
+    "login_email",
+    "login_email_verify",
+    "login_unlock",
+    "oauth",
+    "oauth_token",
+    "profile_edit",
+    "refresh",
+    "register",
+    "register_resend",
+    "register_status",
+    "sessions_revoke",
+    "username_available",
+    "users",
+    "users_lookup",
+    "verify",
+];
+
+/// Limits requests to the wrapped route, with counters kept in the ephemeral store so
+/// that, with Redis, the limit holds across instances.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    name: &'static str,
+    algorithm: Algorithm,
+    key_by: KeyBy,
+    state: Arc<AppState>,
+}
+
+impl RateLimitLayer {
+    pub fn new(
+        name: &'static str,
+        algorithm: Algorithm,
+        key_by: KeyBy,
+        state: Arc<AppState>,
+    ) -> Self {
+        Self {
+            name,
+            algorithm,
+            key_by,
+            state,
+        }
+    }
+
+    /// Like [`RateLimitLayer::new`], but `rate_limits.<name>` in the config overrides the
+    /// algorithm.
+    pub fn from_config(
+        name: &'static str,
+        default: Algorithm,
+        key_by: KeyBy,
+        state: Arc<AppState>,
+    ) -> Self {
+        debug_assert!(
+            NAMES.contains(&name),
+            "rate limit {} is missing from NAMES",
+            name
+        );
+        let algorithm = state
+            .config
+            .rate_limits
+            .get(name)
+            .and_then(|algorithm| Algorithm::parse(algorithm))
+            .unwrap_or(default);
+
+        Self::new(name, algorithm, key_by, state)
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+/// The result of counting a request against a limit.
+pub struct Outcome {
+    pub allowed: bool,
+    pub remaining: u64,
+    pub retry_after_ms: u64,
+    pub reset_ms: u64,
+}
+
+impl RateLimitLayer {
+    async fn hit(&self, key: &str) -> Result<Outcome, Error> {
+        let key = format!("rate_limit:{}:{}", self.name, key);
+
+        self.state.store.hit_rate_limit(&key, &self.algorithm).await
+    }
+
+    fn write_headers(&self, headers: &mut HeaderMap, outcome: &Outcome) {
+        headers.insert("ratelimit-limit", self.algorithm.limit().into());
+        headers.insert("ratelimit-remaining", outcome.remaining.into());
+        headers.insert("ratelimit-reset", outcome.reset_ms.div_ceil(1000).into());
+        if let Ok(policy) = HeaderValue::from_str(&self.algorithm.policy()) {
+            headers.insert("ratelimit-policy", policy);
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for RateLimit<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+
+        Box::pin(async move {
+            let state = &layer.state;
+            let key = layer
+                .key_by
+                .key(&request, &state.keys, state.config.server.trusted_proxies);
+
+            // Fail open: an unavailable store should not take every limited route down.
+            let outcome = match layer.hit(&key).await {
+                Ok(outcome) => outcome,
+                Err(_) => return inner.call(request).await,
+            };
+
+            let mut response = if outcome.allowed {
+                inner.call(request).await?
+            } else {
+                tracing::debug!("rate limit {} exceeded by {}", layer.name, key);
+                Error::RateLimited {
+                    retry_after: outcome.retry_after_ms.div_ceil(1000).max(1),
+                }
+                .into_response()
+            };
+
+            layer.write_headers(response.headers_mut(), &outcome);
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_algorithms() {
+        let Some(Algorithm::TokenBucket {
+            capacity,
+            refill_per_second,
+        }) = Algorithm::parse("token_bucket:10:0.5")
+        else {
+            panic!("token bucket not parsed");
+        };
+        assert_eq!((capacity, refill_per_second), (10, 0.5));
+
+        let Some(Algorithm::SlidingWindow { limit, window }) =
+            Algorithm::parse("sliding_window:5:60")
+        else {
+            panic!("sliding window not parsed");
+        };
+        assert_eq!((limit, window), (5, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn rejects_malformed_algorithms() {
+        for s in [
+            "",
+            "token_bucket:10",
+            "token_bucket:10:1:1",
+            "leaky_bucket:10:1",
+            "token_bucket:ten:1",
+            "sliding_window:5:1.5",
+        ] {
+            assert!(Algorithm::parse(s).is_none(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn rejects_non_positive_algorithms() {
+        for s in [
+            "token_bucket:0:1",
+            "token_bucket:10:0",
+            "token_bucket:10:-1",
+            "token_bucket:10:NaN",
+            "token_bucket:10:inf",
+            "sliding_window:0:60",
+            "sliding_window:5:0",
+            "sliding_window:5:-60",
+        ] {
+            assert!(Algorithm::parse(s).is_none(), "{:?}", s);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::http::{HeaderName, HeaderValue, Request};
+use axum::response::Response;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use rand::Rng;
+use tower::{Layer, Service};
+use tracing::field::Empty;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+
+/// Identifies a request in logs and responses. Available as a request extension.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Takes the id the client sent if it is reasonable, otherwise makes one up.
+    fn from_request(request: &Request<Body>) -> Self {
+        let sent = request
+            .headers()
+            .get(&REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| {
+                (1..=128).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_graphic())
+            });
+
+        RequestId(match sent {
+            Some(id) => id.to_string(),
+            None => format!("{:032x}", rand::thread_rng().gen::<u128>()),
+        })
+    }
+}
+
+/// Handles each request in a span, continuing the trace of an incoming `traceparent`,
+/// and tags it with an `X-Request-Id`. Both are echoed in the response.
+///
+/// Handlers and extractors fill in the `user_id` and `client_id` fields of the span when
+/// they know them, and the route is filled in once the request has been routed.
+#[derive(Clone, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for RequestIdService<S>
+where
+    S: Service<Request<Body>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        let id = RequestId::from_request(&request);
+        let span = tracing::info_span!(
+            "request",
+            method = %request.method(),
+            route = Empty,
+            request_id = %id.0,
+            user_id = Empty,
+            client_id = Empty,
+            status = Empty,
+            otel.name = Empty,
+            otel.kind = "server",
+        );
+        let propagator = TraceContextPropagator::new();
+        span.set_parent(propagator.extract(&HeaderExtractor(request.headers())));
+
+        let header = HeaderValue::from_str(&id.0).expect("request ids are visible ascii");
+        request.extensions_mut().insert(id);
+        let future = span.in_scope(|| self.inner.call(request));
+
+        Box::pin(
+            async move {
+                let mut response = future.await?;
+
+                let span = tracing::Span::current();
+                span.record("status", response.status().as_u16());
+                let headers = response.headers_mut();
+                headers.insert(REQUEST_ID, header);
+                propagator.inject_context(&span.context(), &mut HeaderInjector(headers));
+                if headers.get(TRACESTATE).is_some_and(|value| value.is_empty()) {
+                    headers.remove(TRACESTATE);
+                }
+
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
@@ -0,0 +1,99 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::http::Request;
+use axum::response::Response;
+use tower::{Layer, Service};
+
+/// How many requests have been received and how many are still being handled.
+#[derive(Debug, Default)]
+pub struct RequestStats {
+    total: AtomicU64,
+    in_flight: AtomicU64,
+}
+
+impl RequestStats {
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts a request as in flight until it is dropped, whether it completed or not.
+struct InFlight(Arc<RequestStats>);
+
+impl InFlight {
+    fn start(stats: Arc<RequestStats>) -> Self {
+        stats.total.fetch_add(1, Ordering::Relaxed);
+        stats.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(stats)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts every request into [`RequestStats`], so that shutdown can tell how many are
+/// left.
+#[derive(Clone)]
+pub struct CountRequestsLayer {
+    stats: Arc<RequestStats>,
+}
+
+impl CountRequestsLayer {
+    pub fn new(stats: Arc<RequestStats>) -> Self {
+        Self { stats }
+    }
+}
+
+impl<S> Layer<S> for CountRequestsLayer {
+    type Service = CountRequests<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CountRequests {
+            inner,
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CountRequests<S> {
+    inner: S,
+    stats: Arc<RequestStats>,
+}
+
+impl<S> Service<Request<Body>> for CountRequests<S>
+where
+    S: Service<Request<Body>, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let in_flight = InFlight::start(self.stats.clone());
+        let future = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = future.await;
+            drop(in_flight);
+            response
+        })
+    }
+}
@@ -0,0 +1,924 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::sync::{Mutex, MutexGuard, Weak};
+use std::time::Duration;
+
+use axum::async_trait;
+use chrono::Utc;
+use tokio::time::Instant;
+
+use crate::data::error::Error;
+use crate::data::session::{ClientInfo, Session};
+use crate::middleware::rate_limit::{Algorithm, Outcome};
+use crate::store::{
+    generate_refresh_token, AuthorizationCode, CodeCheck, EphemeralStore, PendingRegistration,
+    TokenPurpose,
+};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Expiring<T> {
+    value: T,
+    expires_at: Option<Instant>,
+}
+
+impl<T> Expiring<T> {
+    fn new(value: T, ttl: Option<Duration>) -> Self {
+        Self {
+            value,
+            expires_at: ttl.map(|ttl| Instant::now() + ttl),
+        }
+    }
+
+    fn is_live(&self, now: Instant) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+struct LoginCode {
+    code: String,
+    uid: i32,
+    attempts: u32,
+}
+
+struct StoredSession {
+    uid: i32,
+    refresh_token: String,
+    name: String,
+    created_at: i64,
+    last_used_at: i64,
+    ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+enum Limit {
+    Bucket { tokens: f64, updated_at: Instant },
+    Window(VecDeque<Instant>),
+}
+
+#[derive(Default)]
+struct State {
+    values: HashMap<String, Expiring<String>>,
+    authorization_codes: HashMap<String, Expiring<AuthorizationCode>>,
+    verifications: HashMap<String, Expiring<PendingRegistration>>,
+    login_codes: HashMap<String, Expiring<LoginCode>>,
+    sessions: HashMap<String, Expiring<StoredSession>>,
+    /// Refresh token to session id.
+    refresh_tokens: HashMap<String, Expiring<String>>,
+    user_sessions: HashMap<i32, HashSet<String>>,
+    limits: HashMap<String, Expiring<Limit>>,
+}
+
+/// Returns the live entry at `key`, dropping it if it has expired.
+fn live<'a, K, T>(map: &'a mut HashMap<K, Expiring<T>>, key: &K) -> Option<&'a mut T>
+where
+    K: Eq + Hash,
+{
+    if map.get(key).is_some_and(|entry| !entry.is_live(Instant::now())) {
+        map.remove(key);
+    }
+
+    map.get_mut(key).map(|entry| &mut entry.value)
+}
+
+/// Removes the entry at `key` and returns it if it was live.
+fn take<K, T>(map: &mut HashMap<K, Expiring<T>>, key: &K) -> Option<T>
+where
+    K: Eq + Hash,
+{
+    map.remove(key)
+        .filter(|entry| entry.is_live(Instant::now()))
+        .map(|entry| entry.value)
+}
+
+impl State {
+    fn sweep(&mut self) {
+        let now = Instant::now();
+        self.values.retain(|_, entry| entry.is_live(now));
+        self.authorization_codes.retain(|_, entry| entry.is_live(now));
+        self.verifications.retain(|_, entry| entry.is_live(now));
+        self.login_codes.retain(|_, entry| entry.is_live(now));
+        self.sessions.retain(|_, entry| entry.is_live(now));
+        self.refresh_tokens.retain(|_, entry| entry.is_live(now));
+        self.limits.retain(|_, entry| entry.is_live(now));
+
+        let sessions = &self.sessions;
+        self.user_sessions.retain(|_, ids| {
+            ids.retain(|id| sessions.contains_key(id));
+            !ids.is_empty()
+        });
+    }
+
+    fn insert_refresh_token(&mut self, token: &str, session: &str, uid: i32, ttl: Duration) {
+        self.refresh_tokens
+            .insert(token.to_string(), Expiring::new(session.to_string(), Some(ttl)));
+        if let Some(stored) = self.sessions.get_mut(session) {
+            stored.value.refresh_token = token.to_string();
+            stored.expires_at = Some(Instant::now() + ttl);
+        }
+        self.user_sessions
+            .entry(uid)
+            .or_default()
+            .insert(session.to_string());
+    }
+
+    fn revoke_session(&mut self, uid: i32, session: &str) -> bool {
+        if live(&mut self.sessions, &session.to_string()).is_none_or(|stored| stored.uid != uid) {
+            return false;
+        }
+
+        if let Some(stored) = self.sessions.remove(session) {
+            self.refresh_tokens.remove(&stored.value.refresh_token);
+        }
+        if let Some(ids) = self.user_sessions.get_mut(&uid) {
+            ids.remove(session);
+        }
+
+        true
+    }
+}
+
+/// Keeps ephemeral state in the memory of this process.
+///
+/// Nothing survives a restart and nothing is shared with other instances, so this is
+/// only fit for running a single node without Redis.
+pub struct MemoryStore {
+    state: Mutex<State>,
+    refresh_token_ttl: Duration,
+}
+
+impl MemoryStore {
+    pub fn new(refresh_token_ttl: Duration) -> Self {
+        MemoryStore {
+            state: Mutex::default(),
+            refresh_token_ttl,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Periodically drops expired entries, until the store is dropped.
+pub async fn sweep(store: Weak<MemoryStore>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let Some(store) = store.upgrade() else {
+            return;
+        };
+        store.state().sweep();
+    }
+}
+
+#[async_trait]
+impl EphemeralStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        Ok(live(&mut self.state().values, &key.to_string()).cloned())
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), Error> {
+        self.state()
+            .values
+            .insert(key.to_string(), Expiring::new(value.to_string(), ttl));
+
+        Ok(())
+    }
+
+    async fn set_nx(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, Error> {
+        let mut state = self.state();
+        if live(&mut state.values, &key.to_string()).is_some() {
+            return Ok(false);
+        }
+
+        state
+            .values
+            .insert(key.to_string(), Expiring::new(value.to_string(), Some(ttl)));
+        Ok(true)
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>, Error> {
+        let now = Instant::now();
+
+        Ok(self
+            .state()
+            .values
+            .get(key)
+            .and_then(|entry| entry.expires_at)
+            .and_then(|expires_at| expires_at.checked_duration_since(now))
+            .filter(|ttl| !ttl.is_zero()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        self.state().values.remove(key);
+
+        Ok(())
+    }
+
+    async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, Error> {
+        let mut state = self.state();
+        let count = live(&mut state.values, &key.to_string())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(0)
+            + 1;
+
+        state
+            .values
+            .insert(key.to_string(), Expiring::new(count.to_string(), Some(ttl)));
+        Ok(count)
+    }
+
+    async fn store_token(
+        &self,
+        purpose: TokenPurpose,
+        token: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        self.set(&format!("{}:{}", purpose.prefix(), token), value, Some(ttl))
+            .await
+    }
+
+    async fn consume_token(
+        &self,
+        purpose: TokenPurpose,
+        token: &str,
+    ) -> Result<Option<String>, Error> {
+        let key = format!("{}:{}", purpose.prefix(), token);
+
+        Ok(take(&mut self.state().values, &key))
+    }
+
+    async fn store_authorization_code(
+        &self,
+        code: &str,
+        grant: &AuthorizationCode,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        self.state()
+            .authorization_codes
+            .insert(code.to_string(), Expiring::new(grant.clone(), Some(ttl)));
+
+        Ok(())
+    }
+
+    async fn consume_authorization_code(
+        &self,
+        code: &str,
+    ) -> Result<Option<AuthorizationCode>, Error> {
+        Ok(take(&mut self.state().authorization_codes, &code.to_string()))
+    }
+
+    async fn store_verification(
+        &self,
+        token: &str,
+        registration: &PendingRegistration,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let mut state = self.state();
+        let pending_key = format!("email_verify_pending:{}", registration.email);
+
+        if let Some(previous) = take(&mut state.values, &pending_key) {
+            if let Some(previous) = take(&mut state.verifications, &previous) {
+                let claim = format!("username_pending:{}", previous.username);
+                if live(&mut state.values, &claim).is_some_and(|email| *email == previous.email) {
+                    state.values.remove(&claim);
+                }
+            }
+        }
+        state.verifications.insert(
+            token.to_string(),
+            Expiring::new(registration.clone(), Some(ttl)),
+        );
+        state
+            .values
+            .insert(pending_key, Expiring::new(token.to_string(), Some(ttl)));
+        state.values.insert(
+            format!("username_pending:{}", registration.username),
+            Expiring::new(registration.email.clone(), Some(ttl)),
+        );
+
+        Ok(())
+    }
+
+    async fn verification(&self, token: &str) -> Result<Option<PendingRegistration>, Error> {
+        Ok(live(&mut self.state().verifications, &token.to_string()).cloned())
+    }
+
+    async fn consume_verification(
+        &self,
+        token: &str,
+    ) -> Result<Option<PendingRegistration>, Error> {
+        let mut state = self.state();
+        let Some(registration) = take(&mut state.verifications, &token.to_string()) else {
+            return Ok(None);
+        };
+
+        let pending_key = format!("email_verify_pending:{}", registration.email);
+        if live(&mut state.values, &pending_key).is_some_and(|pending| pending == token) {
+            state.values.remove(&pending_key);
+        }
+
+        Ok(Some(registration))
+    }
+
+    async fn pending_verification(&self, email: &str) -> Result<Option<String>, Error> {
+        self.get(&format!("email_verify_pending:{}", email)).await
+    }
+
+    async fn username_claim(&self, username: &str) -> Result<Option<String>, Error> {
+        self.get(&format!("username_pending:{}", username)).await
+    }
+
+    async fn release_username_claim(&self, username: &str) -> Result<(), Error> {
+        self.delete(&format!("username_pending:{}", username)).await
+    }
+
+    async fn store_login_code(
+        &self,
+        email: &str,
+        code: &str,
+        uid: i32,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let code = LoginCode {
+            code: code.to_string(),
+            uid,
+            attempts: 0,
+        };
+        self.state()
+            .login_codes
+            .insert(email.to_string(), Expiring::new(code, Some(ttl)));
+
+        Ok(())
+    }
+
+    async fn check_login_code(
+        &self,
+        email: &str,
+        code: &str,
+        max_attempts: u32,
+    ) -> Result<CodeCheck, Error> {
+        let mut state = self.state();
+        let email = email.to_string();
+        let Some(pending) = live(&mut state.login_codes, &email) else {
+            return Ok(CodeCheck::Missing);
+        };
+
+        if pending.attempts >= max_attempts {
+            state.login_codes.remove(&email);
+            return Ok(CodeCheck::Missing);
+        }
+        if pending.code == code {
+            let uid = pending.uid;
+            state.login_codes.remove(&email);
+            return Ok(CodeCheck::Correct { uid });
+        }
+
+        pending.attempts += 1;
+        let check = CodeCheck::Incorrect {
+            uid: pending.uid,
+            attempts: pending.attempts,
+        };
+        if pending.attempts >= max_attempts {
+            state.login_codes.remove(&email);
+        }
+
+        Ok(check)
+    }
+
+    async fn create_session(
+        &self,
+        uid: i32,
+        client: &ClientInfo,
+        name: Option<String>,
+    ) -> Result<(String, String), Error> {
+        let session = generate_refresh_token();
+        let token = generate_refresh_token();
+        let now = Utc::now().timestamp();
+
+        let mut state = self.state();
+        state.sessions.insert(
+            session.clone(),
+            Expiring::new(
+                StoredSession {
+                    uid,
+                    refresh_token: token.clone(),
+                    name: name.unwrap_or_else(|| client.device_name()),
+                    created_at: now,
+                    last_used_at: now,
+                    ip: client.ip.clone(),
+                    user_agent: client.user_agent.clone(),
+                },
+                Some(self.refresh_token_ttl),
+            ),
+        );
+        state.insert_refresh_token(&token, &session, uid, self.refresh_token_ttl);
+
+        Ok((session, token))
+    }
+
+    async fn rotate_refresh_token(
+        &self,
+        token: &str,
+        client: &ClientInfo,
+    ) -> Result<Option<(i32, String, String)>, Error> {
+        let mut state = self.state();
+        let Some(session) = take(&mut state.refresh_tokens, &token.to_string()) else {
+            return Ok(None);
+        };
+        let Some(stored) = live(&mut state.sessions, &session) else {
+            return Ok(None);
+        };
+
+        stored.last_used_at = Utc::now().timestamp();
+        if client.ip.is_some() {
+            stored.ip = client.ip.clone();
+        }
+        if client.user_agent.is_some() {
+            stored.user_agent = client.user_agent.clone();
+        }
+        let uid = stored.uid;
+
+        let new_token = generate_refresh_token();
+        state.insert_refresh_token(&new_token, &session, uid, self.refresh_token_ttl);
+
+        Ok(Some((uid, session, new_token)))
+    }
+
+    async fn list_sessions(
+        &self,
+        uid: i32,
+        current: Option<&str>,
+    ) -> Result<Vec<Session>, Error> {
+        let mut state = self.state();
+        let ids: Vec<String> = state
+            .user_sessions
+            .get(&uid)
+            .map(|ids| ids.iter().cloned().collect())
+            .unwrap_or_default();
+        let mut sessions = Vec::with_capacity(ids.len());
+
+        for id in ids {
+            let Some(stored) = live(&mut state.sessions, &id).filter(|stored| stored.uid == uid)
+            else {
+                if let Some(ids) = state.user_sessions.get_mut(&uid) {
+                    ids.remove(&id);
+                }
+                continue;
+            };
+
+            sessions.push(Session {
+                current: current == Some(id.as_str()),
+                name: stored.name.clone(),
+                created_at: stored.created_at,
+                last_used_at: stored.last_used_at,
+                ip: stored.ip.clone(),
+                user_agent: stored.user_agent.clone(),
+                id,
+            });
+        }
+
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_used_at));
+        Ok(sessions)
+    }
+
+    async fn revoke_session(&self, uid: i32, session: &str) -> Result<bool, Error> {
+        Ok(self.state().revoke_session(uid, session))
+    }
+
+    async fn revoke_all_sessions(&self, uid: i32) -> Result<(), Error> {
+        let mut state = self.state();
+        for id in state.user_sessions.remove(&uid).unwrap_or_default() {
+            state.revoke_session(uid, &id);
+        }
+
+        Ok(())
+    }
+
+    async fn hit_rate_limit(&self, key: &str, algorithm: &Algorithm) -> Result<Outcome, Error> {
+        let mut state = self.state();
+        let now = Instant::now();
+        let key = key.to_string();
+
+        let outcome = match *algorithm {
+            Algorithm::TokenBucket {
+                capacity,
+                refill_per_second,
+            } => {
+                let capacity = capacity as f64;
+                let rate = refill_per_second / 1000.0;
+                let (mut tokens, updated_at) = match live(&mut state.limits, &key) {
+                    Some(Limit::Bucket { tokens, updated_at }) => (*tokens, *updated_at),
+                    _ => (capacity, now),
+                };
+
+                let elapsed = now.duration_since(updated_at).as_millis() as f64;
+                tokens = capacity.min(tokens + elapsed * rate);
+                let allowed = tokens >= 1.0;
+                if allowed {
+                    tokens -= 1.0;
+                }
+
+                let ttl = Duration::from_millis((capacity / rate).ceil() as u64);
+                state.limits.insert(
+                    key,
+                    Expiring::new(
+                        Limit::Bucket {
+                            tokens,
+                            updated_at: now,
+                        },
+                        Some(ttl),
+                    ),
+                );
+
+                Outcome {
+                    allowed,
+                    remaining: tokens.floor() as u64,
+                    retry_after_ms: if allowed {
+                        0
+                    } else {
+                        ((1.0 - tokens) / rate).ceil() as u64
+                    },
+                    reset_ms: ((capacity - tokens) / rate).ceil() as u64,
+                }
+            }
+            Algorithm::SlidingWindow { limit, window } => {
+                let mut hits = match take(&mut state.limits, &key) {
+                    Some(Limit::Window(hits)) => hits,
+                    _ => VecDeque::new(),
+                };
+
+                while hits
+                    .front()
+                    .is_some_and(|hit| now.duration_since(*hit) >= window)
+                {
+                    hits.pop_front();
+                }
+                let allowed = hits.len() < limit as usize;
+                if allowed {
+                    hits.push_back(now);
+                }
+
+                let reset_ms = hits
+                    .front()
+                    .map(|oldest| (*oldest + window).duration_since(now).as_millis() as u64)
+                    .unwrap_or(0);
+                let remaining = (limit as usize).saturating_sub(hits.len()) as u64;
+                state
+                    .limits
+                    .insert(key, Expiring::new(Limit::Window(hits), Some(window)));
+
+                Outcome {
+                    allowed,
+                    remaining,
+                    retry_after_ms: if allowed { 0 } else { reset_ms },
+                    reset_ms,
+                }
+            }
+        };
+
+        Ok(outcome)
+    }
+
+    /// There is nobody to announce to without Redis, so the event is only logged.
+    async fn publish_account_event(&self, fields: &[(&str, String)]) -> Result<(), Error> {
+        tracing::info!("account event: {:?}", fields);
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use tokio::time::advance;
+
+    use super::*;
+
+    const SHORT: Duration = Duration::from_millis(30);
+
+    fn store() -> Arc<MemoryStore> {
+        Arc::new(MemoryStore::new(Duration::from_secs(60)))
+    }
+
+    fn registration(email: &str, username: &str) -> PendingRegistration {
+        PendingRegistration {
+            email: email.to_string(),
+            hashed_password: "hash".to_string(),
+            username: username.to_string(),
+            display_name: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tokens_are_consumed_exactly_once() {
+        let store = store();
+        store
+            .store_token(TokenPurpose::EmailLogin, "token", "42", SHORT)
+            .await
+            .unwrap();
+
+        let consumers: Vec<_> = (0..16)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(
+                    async move { store.consume_token(TokenPurpose::EmailLogin, "token").await },
+                )
+            })
+            .collect();
+        let mut consumed = Vec::new();
+        for consumer in consumers {
+            consumed.extend(consumer.await.unwrap().unwrap());
+        }
+
+        assert_eq!(consumed, ["42"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tokens_are_scoped_to_their_purpose_and_expire() {
+        let store = store();
+        store
+            .store_token(TokenPurpose::LoginUnlock, "a", "x", SHORT)
+            .await
+            .unwrap();
+        store
+            .store_token(TokenPurpose::LoginUnlock, "b", "y", SHORT)
+            .await
+            .unwrap();
+
+        let wrong_purpose = store.consume_token(TokenPurpose::SessionRevoke, "a").await;
+        assert_eq!(wrong_purpose.unwrap(), None);
+        let right_purpose = store.consume_token(TokenPurpose::LoginUnlock, "a").await;
+        assert_eq!(right_purpose.unwrap().as_deref(), Some("x"));
+
+        advance(SHORT * 2).await;
+        let expired = store.consume_token(TokenPurpose::LoginUnlock, "b").await;
+        assert_eq!(expired.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn authorization_codes_are_consumed_once() {
+        let store = store();
+        let grant = AuthorizationCode {
+            client_id: 1,
+            scopes: "profile".to_string(),
+            uid: 42,
+        };
+        store
+            .store_authorization_code("code", &grant, SHORT)
+            .await
+            .unwrap();
+
+        let consumed = store.consume_authorization_code("code").await.unwrap();
+        assert_eq!(consumed.map(|grant| grant.uid), Some(42));
+        let again = store.consume_authorization_code("code").await.unwrap();
+        assert!(again.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_new_verification_replaces_the_previous_one() {
+        let store = store();
+        let ttl = Duration::from_secs(60);
+        store
+            .store_verification("first", &registration("a@b.c", "alice"), ttl)
+            .await
+            .unwrap();
+        store
+            .store_verification("second", &registration("a@b.c", "alice"), ttl)
+            .await
+            .unwrap();
+
+        assert!(store.verification("first").await.unwrap().is_none());
+        let pending = store.pending_verification("a@b.c").await.unwrap();
+        assert_eq!(pending.as_deref(), Some("second"));
+        let claim = store.username_claim("alice").await.unwrap();
+        assert_eq!(claim.as_deref(), Some("a@b.c"));
+
+        let consumed = store.consume_verification("second").await.unwrap();
+        assert_eq!(consumed.map(|r| r.username).as_deref(), Some("alice"));
+        assert!(store
+            .consume_verification("second")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store.pending_verification("a@b.c").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn a_new_verification_releases_the_previous_username() {
+        let store = store();
+        let ttl = Duration::from_secs(60);
+        store
+            .store_verification("first", &registration("a@b.c", "alice"), ttl)
+            .await
+            .unwrap();
+        store
+            .store_verification("second", &registration("a@b.c", "alicia"), ttl)
+            .await
+            .unwrap();
+
+        assert!(store.username_claim("alice").await.unwrap().is_none());
+        let claim = store.username_claim("alicia").await.unwrap();
+        assert_eq!(claim.as_deref(), Some("a@b.c"));
+    }
+
+    #[tokio::test]
+    async fn login_codes_are_discarded_after_too_many_guesses() {
+        let store = store();
+        let ttl = Duration::from_secs(60);
+        store
+            .store_login_code("a@b.c", "123456", 42, ttl)
+            .await
+            .unwrap();
+
+        let first = store.check_login_code("a@b.c", "000000", 2).await.unwrap();
+        assert_eq!(
+            first,
+            CodeCheck::Incorrect {
+                uid: 42,
+                attempts: 1
+            }
+        );
+        let second = store.check_login_code("a@b.c", "000000", 2).await.unwrap();
+        assert_eq!(
+            second,
+            CodeCheck::Incorrect {
+                uid: 42,
+                attempts: 2
+            }
+        );
+        let right = store.check_login_code("a@b.c", "123456", 2).await.unwrap();
+        assert_eq!(right, CodeCheck::Missing);
+
+        store
+            .store_login_code("a@b.c", "123456", 42, ttl)
+            .await
+            .unwrap();
+        let right = store.check_login_code("a@b.c", "123456", 2).await.unwrap();
+        assert_eq!(right, CodeCheck::Correct { uid: 42 });
+        let reused = store.check_login_code("a@b.c", "123456", 2).await.unwrap();
+        assert_eq!(reused, CodeCheck::Missing);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn set_nx_only_stores_absent_keys() {
+        let store = store();
+
+        assert!(store.set_nx("key", "first", SHORT).await.unwrap());
+        assert!(!store.set_nx("key", "second", SHORT).await.unwrap());
+        assert_eq!(store.get("key").await.unwrap().as_deref(), Some("first"));
+        assert!(store
+            .ttl("key")
+            .await
+            .unwrap()
+            .is_some_and(|ttl| ttl <= SHORT));
+
+        advance(SHORT * 2).await;
+        assert!(store.ttl("key").await.unwrap().is_none());
+        assert!(store.set_nx("key", "third", SHORT).await.unwrap());
+        assert_eq!(store.get("key").await.unwrap().as_deref(), Some("third"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn increment_restarts_the_ttl() {
+        let store = store();
+
+        assert_eq!(store.increment("counter", SHORT * 3).await.unwrap(), 1);
+        advance(SHORT * 2).await;
+        assert_eq!(store.increment("counter", SHORT * 3).await.unwrap(), 2);
+        advance(SHORT * 2).await;
+        assert_eq!(store.increment("counter", SHORT * 3).await.unwrap(), 3);
+
+        advance(SHORT * 4).await;
+        assert_eq!(store.increment("counter", SHORT * 3).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn refresh_tokens_rotate_once() {
+        let store = store();
+        let client = ClientInfo {
+            ip: Some("10.0.0.1".to_string()),
+            user_agent: None,
+        };
+        let (session, token) = store
+            .create_session(42, &ClientInfo::default(), None)
+            .await
+            .unwrap();
+
+        let (uid, rotated_session, new_token) = store
+            .rotate_refresh_token(&token, &client)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((uid, rotated_session.as_str()), (42, session.as_str()));
+        assert_ne!(new_token, token);
+        assert!(store
+            .rotate_refresh_token(&token, &client)
+            .await
+            .unwrap()
+            .is_none());
+
+        let sessions = store.list_sessions(42, Some(&session)).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].current);
+        assert_eq!(sessions[0].ip.as_deref(), Some("10.0.0.1"));
+
+        assert!(!store.revoke_session(7, &session).await.unwrap());
+        assert!(store.revoke_session(42, &session).await.unwrap());
+        assert!(store
+            .rotate_refresh_token(&new_token, &client)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store.list_sessions(42, None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn revoking_all_sessions_leaves_other_users_alone() {
+        let store = store();
+        let client = ClientInfo::default();
+        let (_, first) = store.create_session(42, &client, None).await.unwrap();
+        let (_, second) = store.create_session(42, &client, None).await.unwrap();
+        let (_, other) = store.create_session(7, &client, None).await.unwrap();
+
+        store.revoke_all_sessions(42).await.unwrap();
+
+        assert!(store
+            .rotate_refresh_token(&first, &client)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store
+            .rotate_refresh_token(&second, &client)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(store
+            .rotate_refresh_token(&other, &client)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn token_bucket_refills_over_time() {
+        let store = store();
+        let algorithm = Algorithm::TokenBucket {
+            capacity: 2,
+            refill_per_second: 20.0,
+        };
+
+        let first = store.hit_rate_limit("key", &algorithm).await.unwrap();
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 1);
+        let second = store.hit_rate_limit("key", &algorithm).await.unwrap();
+        assert!(second.allowed);
+        assert_eq!(second.remaining, 0);
+
+        let denied = store.hit_rate_limit("key", &algorithm).await.unwrap();
+        assert!(!denied.allowed);
+        assert!(denied.retry_after_ms > 0 && denied.retry_after_ms <= 50);
+        assert!(denied.reset_ms <= 100);
+
+        advance(Duration::from_millis(60)).await;
+        assert!(
+            store
+                .hit_rate_limit("key", &algorithm)
+                .await
+                .unwrap()
+                .allowed
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sliding_window_forgets_old_hits() {
+        let store = store();
+        let window = SHORT * 2;
+        let algorithm = Algorithm::SlidingWindow { limit: 2, window };
+
+        for remaining in [1, 0] {
+            let outcome = store.hit_rate_limit("key", &algorithm).await.unwrap();
+            assert!(outcome.allowed);
+            assert_eq!(outcome.remaining, remaining);
+        }
+
+        let denied = store.hit_rate_limit("key", &algorithm).await.unwrap();
+        assert!(!denied.allowed);
+        assert_eq!(denied.remaining, 0);
+        let retry_after = Duration::from_millis(denied.retry_after_ms);
+        assert!(!retry_after.is_zero() && retry_after <= window);
+
+        advance(window + SHORT).await;
+        let outcome = store.hit_rate_limit("key", &algorithm).await.unwrap();
+        assert!(outcome.allowed);
+        assert_eq!(outcome.remaining, 1);
+    }
+}
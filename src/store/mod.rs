@@ -0,0 +1,221 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+use crate::config::{Config, StoreBackend};
+use crate::data::error::Error;
+use crate::data::session::{ClientInfo, Session};
+use crate::middleware::rate_limit::{Algorithm, Outcome};
+
+pub mod memory;
+pub mod redis;
+
+/// Single-use tokens handed out in links, by what they are for.
+#[derive(Debug, Clone, Copy)]
+pub enum TokenPurpose {
+    /// Signs in the user id it holds.
+    EmailLogin,
+    /// Lifts the lockout of the email it holds.
+    LoginUnlock,
+    /// Revokes the session it holds, as `uid:sid`.
+    SessionRevoke,
+}
+
+impl TokenPurpose {
+    fn prefix(self) -> &'static str {
+        match self {
+            TokenPurpose::EmailLogin => "email_login",
+            TokenPurpose::LoginUnlock => "login_unlock",
+            TokenPurpose::SessionRevoke => "session_revoke",
+        }
+    }
+}
+
+/// What an OAuth authorization code was issued for.
+#[derive(Debug, Clone)]
+pub struct AuthorizationCode {
+    pub client_id: i32,
+    /// Space separated, as requested.
+    pub scopes: String,
+    pub uid: i32,
+}
+
+/// A registration waiting for its email to be verified.
+#[derive(Debug, Clone)]
+pub struct PendingRegistration {
+    pub email: String,
+    pub hashed_password: String,
+    pub username: String,
+    pub display_name: Option<String>,
+}
+
+/// The result of checking an email login code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeCheck {
+    /// No code is pending, or it has been guessed at too often.
+    Missing,
+    /// The code was right and has been consumed.
+    Correct { uid: i32 },
+    /// The code was wrong; `attempts` wrong guesses have been made so far.
+    Incorrect { uid: i32, attempts: u32 },
+}
+
+/// Short-lived authentication state: sessions, one-time tokens and codes, counters and
+/// cooldowns.
+///
+/// Every `consume_*` operation is atomic, so a token or code can only ever be used once.
+#[async_trait]
+pub trait EphemeralStore: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, Error>;
+
+    /// Stores `value` under `key`, for `ttl` or until deleted.
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), Error>;
+
+    /// Stores `value` under `key` unless it already exists. Returns whether it was stored.
+    async fn set_nx(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, Error>;
+
+    /// How long `key` has left, or `None` if it does not exist or never expires.
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>, Error>;
+
+    async fn delete(&self, key: &str) -> Result<(), Error>;
+
+    /// Increments the counter at `key` and restarts its `ttl`, returning the new count.
+    async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, Error>;
+
+    async fn store_token(
+        &self,
+        purpose: TokenPurpose,
+        token: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<(), Error>;
+
+    async fn consume_token(&self, purpose: TokenPurpose, token: &str)
+        -> Result<Option<String>, Error>;
+
+    async fn store_authorization_code(
+        &self,
+        code: &str,
+        grant: &AuthorizationCode,
+        ttl: Duration,
+    ) -> Result<(), Error>;
+
+    async fn consume_authorization_code(
+        &self,
+        code: &str,
+    ) -> Result<Option<AuthorizationCode>, Error>;
+
+    /// Stores a verification token for a registration and reserves its username
+    /// meanwhile, invalidating any token previously issued for the same email along with
+    /// the username that one reserved.
+    async fn store_verification(
+        &self,
+        token: &str,
+        registration: &PendingRegistration,
+        ttl: Duration,
+    ) -> Result<(), Error>;
+
+    async fn verification(&self, token: &str) -> Result<Option<PendingRegistration>, Error>;
+
+    async fn consume_verification(
+        &self,
+        token: &str,
+    ) -> Result<Option<PendingRegistration>, Error>;
+
+    /// The verification token currently pending for `email`, if any.
+    async fn pending_verification(&self, email: &str) -> Result<Option<String>, Error>;
+
+    /// The email of the pending registration that reserved `username`, if any.
+    async fn username_claim(&self, username: &str) -> Result<Option<String>, Error>;
+
+    async fn release_username_claim(&self, username: &str) -> Result<(), Error>;
+
+    /// Stores a login code for `email`, replacing any previous one.
+    async fn store_login_code(
+        &self,
+        email: &str,
+        code: &str,
+        uid: i32,
+        ttl: Duration,
+    ) -> Result<(), Error>;
+
+    /// Checks a guess of the login code of `email`, consuming the code if it is right
+    /// and discarding it after `max_attempts` wrong guesses.
+    async fn check_login_code(
+        &self,
+        email: &str,
+        code: &str,
+        max_attempts: u32,
+    ) -> Result<CodeCheck, Error>;
+
+    /// Creates a new session for `uid` and returns its id together with its refresh token.
+    async fn create_session(
+        &self,
+        uid: i32,
+        client: &ClientInfo,
+        name: Option<String>,
+    ) -> Result<(String, String), Error>;
+
+    /// Consumes a refresh token and issues a new one for the same session.
+    ///
+    /// Returns the user id, the session id and the new refresh token, or `None` if the
+    /// refresh token or its session no longer exists.
+    async fn rotate_refresh_token(
+        &self,
+        token: &str,
+        client: &ClientInfo,
+    ) -> Result<Option<(i32, String, String)>, Error>;
+
+    /// Lists the live sessions of `uid`, most recently used first.
+    async fn list_sessions(&self, uid: i32, current: Option<&str>)
+        -> Result<Vec<Session>, Error>;
+
+    /// Deletes a session of `uid` and its refresh token. Returns `false` if no such
+    /// session exists.
+    async fn revoke_session(&self, uid: i32, session: &str) -> Result<bool, Error>;
+
+    async fn revoke_all_sessions(&self, uid: i32) -> Result<(), Error>;
+
+    /// Counts a request against the limit at `key`.
+    async fn hit_rate_limit(&self, key: &str, algorithm: &Algorithm) -> Result<Outcome, Error>;
+
+    /// Announces an account lifecycle event to connected services.
+    async fn publish_account_event(&self, fields: &[(&str, String)]) -> Result<(), Error>;
+
+    /// Checks that the store can be reached.
+    async fn ping(&self) -> Result<(), Error>;
+}
+
+/// Sets up the configured backend: Redis, or memory, which only works for a single
+/// instance.
+pub async fn from_config(config: &Config) -> Arc<dyn EphemeralStore> {
+    let refresh_token_ttl = Duration::from_secs(config.auth.refresh_token_ttl_seconds);
+
+    match config.store.backend {
+        StoreBackend::Redis => {
+            let url = config.store.redis_url.as_deref().unwrap_or_default();
+            let client = ::redis::Client::open(url).expect("REDIS_URL illegal");
+            let store = redis::RedisStore::connect(client, &config.store, refresh_token_ttl)
+                .await
+                .expect("failed to connect redis");
+            Arc::new(store)
+        }
+        StoreBackend::Memory => {
+            tracing::warn!("keeping sessions in memory, they will not survive a restart");
+            let store = Arc::new(memory::MemoryStore::new(refresh_token_ttl));
+            tokio::spawn(memory::sweep(Arc::downgrade(&store)));
+            store
+        }
+    }
+}
+
+pub fn generate_refresh_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
+}
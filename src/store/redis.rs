@@ -0,0 +1,747 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use axum::async_trait;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use rand::Rng;
+use redis::aio::{ConnectionLike, ConnectionManager, ConnectionManagerConfig};
+use redis::streams::StreamMaxlen;
+use redis::{
+    Arg, AsyncCommands, Cmd, ExistenceCheck, Pipeline, RedisFuture, SetExpiry, SetOptions, Value,
+};
+use tracing::Instrument;
+
+use crate::config::StoreConfig;
+use crate::data::error::Error;
+use crate::data::session::{ClientInfo, Session};
+use crate::middleware::rate_limit::{Algorithm, Outcome};
+use crate::store::{
+    generate_refresh_token, AuthorizationCode, CodeCheck, EphemeralStore, PendingRegistration,
+    TokenPurpose,
+};
+use crate::utils::db::StanderizeError;
+
+/// Redis stream that account lifecycle events are published to, so that connected
+/// services can purge the data they hold about a deleted user.
+pub const ACCOUNT_EVENTS_STREAM: &str = "account_events";
+
+lazy_static! {
+    static ref TOKEN_BUCKET: redis::Script = redis::Script::new(
+        r"
+        local capacity = tonumber(ARGV[1])
+        local rate = tonumber(ARGV[2])
+        local time = redis.call('TIME')
+        local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+        local state = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+        local tokens = tonumber(state[1]) or capacity
+        local ts = tonumber(state[2]) or now
+        tokens = math.min(capacity, tokens + (now - ts) * rate)
+        local allowed = 0
+        if tokens >= 1 then
+            tokens = tokens - 1
+            allowed = 1
+        end
+        redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+        redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / rate))
+        local retry = 0
+        if allowed == 0 then
+            retry = math.ceil((1 - tokens) / rate)
+        end
+        return {allowed, math.floor(tokens), retry, math.ceil((capacity - tokens) / rate)}
+        ",
+    );
+    static ref SLIDING_WINDOW: redis::Script = redis::Script::new(
+        r"
+        local limit = tonumber(ARGV[1])
+        local window = tonumber(ARGV[2])
+        local time = redis.call('TIME')
+        local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+        redis.call('ZREMRANGEBYSCORE', KEYS[1], 0, now - window)
+        local count = redis.call('ZCARD', KEYS[1])
+        local allowed = 0
+        if count < limit then
+            redis.call('ZADD', KEYS[1], now, now .. '-' .. ARGV[3])
+            count = count + 1
+            allowed = 1
+        end
+        redis.call('PEXPIRE', KEYS[1], window)
+        local reset = 0
+        local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+        if oldest[2] then
+            reset = tonumber(oldest[2]) + window - now
+        end
+        local retry = 0
+        if allowed == 0 then
+            retry = reset
+        end
+        return {allowed, limit - count, retry, reset}
+        ",
+    );
+    /// Returns `{0}` if no code is pending, `{1, uid}` if the guess was right and
+    /// `{2, uid, attempts}` if it was wrong.
+    static ref CHECK_LOGIN_CODE: redis::Script = redis::Script::new(
+        r"
+        local max = tonumber(ARGV[2])
+        local v = redis.call('HMGET', KEYS[1], 'code', 'uid', 'attempts')
+        if not v[1] or not v[2] or not v[3] then
+            return {0}
+        end
+        local uid = tonumber(v[2])
+        if tonumber(v[3]) >= max then
+            redis.call('DEL', KEYS[1])
+            return {0}
+        end
+        if v[1] == ARGV[1] then
+            redis.call('DEL', KEYS[1])
+            return {1, uid}
+        end
+        local attempts = redis.call('HINCRBY', KEYS[1], 'attempts', 1)
+        if attempts >= max then
+            redis.call('DEL', KEYS[1])
+        end
+        return {2, uid, attempts}
+        ",
+    );
+    /// Replaces the verification token pending for an email, releasing the username
+    /// claimed by the replaced one if the email still holds it.
+    static ref STORE_VERIFICATION: redis::Script = redis::Script::new(
+        r"
+        local ttl = tonumber(ARGV[2])
+        local previous = redis.call('GET', KEYS[1])
+        if previous then
+            local previous_key = 'email_verify:' .. previous
+            local username = redis.call('HGET', previous_key, 'username')
+            if username then
+                local claim = 'username_pending:' .. username
+                if redis.call('GET', claim) == ARGV[3] then
+                    redis.call('DEL', claim)
+                end
+            end
+            redis.call('DEL', previous_key)
+        end
+        redis.call('HSET', KEYS[2], unpack(ARGV, 4))
+        redis.call('PEXPIRE', KEYS[2], ttl)
+        redis.call('SET', KEYS[1], ARGV[1], 'PX', ttl)
+        redis.call('SET', KEYS[3], ARGV[3], 'PX', ttl)
+        return 1
+        ",
+    );
+}
+
+/// Runs every command, or pipeline of them, in a span.
+#[derive(Clone)]
+struct TracedConnection(ConnectionManager);
+
+impl TracedConnection {
+    fn span(operation: &str) -> tracing::Span {
+        tracing::info_span!(
+            "redis",
+            otel.name = %format!("redis {}", operation),
+            otel.kind = "client",
+            db.system = "redis",
+            db.operation = operation,
+        )
+    }
+}
+
+impl ConnectionLike for TracedConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        let span = Self::span(command_name(cmd));
+        Box::pin(self.0.req_packed_command(cmd).instrument(span))
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        pipeline: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        let names: Vec<&str> = pipeline.cmd_iter().map(command_name).collect();
+        let span = Self::span(&names.join(" "));
+        Box::pin(self.0.req_packed_commands(pipeline, offset, count).instrument(span))
+    }
+
+    fn get_db(&self) -> i64 {
+        self.0.get_db()
+    }
+}
+
+fn command_name(cmd: &Cmd) -> &str {
+    match cmd.args_iter().next() {
+        Some(Arg::Simple(name)) => std::str::from_utf8(name).unwrap_or("?"),
+        _ => "?",
+    }
+}
+
+/// Keeps ephemeral state in Redis, shared by every instance.
+#[derive(Clone)]
+pub struct RedisStore {
+    /// A multiplexed connection that reconnects by itself. Cloning it is cheap and
+    /// shares the underlying connection, so every operation just takes a clone.
+    conn: TracedConnection,
+    /// In seconds.
+    refresh_token_ttl: u64,
+}
+
+impl RedisStore {
+    /// Connects to Redis, with the timeouts and reconnection backoff of `config`.
+    pub async fn connect(
+        client: redis::Client,
+        config: &StoreConfig,
+        refresh_token_ttl: Duration,
+    ) -> redis::RedisResult<Self> {
+        let manager_config = ConnectionManagerConfig::new()
+            .set_connection_timeout(Duration::from_millis(config.connect_timeout_ms))
+            .set_response_timeout(Duration::from_millis(config.response_timeout_ms))
+            .set_number_of_retries(config.reconnect_retries)
+            .set_max_delay(config.reconnect_max_delay_ms);
+
+        Ok(Self {
+            conn: TracedConnection(
+                ConnectionManager::new_with_config(client, manager_config).await?,
+            ),
+            refresh_token_ttl: refresh_token_ttl.as_secs(),
+        })
+    }
+
+    /// Stores `refresh:{token}` pointing at the session and keeps the session and the
+    /// per-user session index alive for as long as the refresh token is.
+    async fn insert_refresh_token(
+        &self,
+        token: &str,
+        session: &str,
+        uid: i32,
+    ) -> Result<(), Error> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        self.queue_refresh_token(&mut pipe, token, session, uid);
+        let _: () = pipe.query_async(&mut self.conn.clone()).await.warn_err()?;
+
+        Ok(())
+    }
+
+    /// Queues the commands of [`Self::insert_refresh_token`] on `pipe`.
+    fn queue_refresh_token(
+        &self,
+        pipe: &mut redis::Pipeline,
+        token: &str,
+        session: &str,
+        uid: i32,
+    ) {
+        let refresh_key = format!("refresh:{}", token);
+        let session_key = format!("session:{}", session);
+        let index_key = format!("user_sessions:{}", uid);
+
+        pipe.set_ex(&refresh_key, session, self.refresh_token_ttl)
+            .ignore()
+            .hset(&session_key, "refresh_token", token)
+            .ignore()
+            .expire(&session_key, self.refresh_token_ttl as i64)
+            .ignore()
+            .sadd(&index_key, session)
+            .ignore()
+            .expire(&index_key, self.refresh_token_ttl as i64)
+            .ignore();
+    }
+
+    /// Atomically reads and deletes a string key.
+    async fn take(&self, key: &str) -> Result<Option<String>, Error> {
+        let (value,): (Option<String>,) = redis::pipe()
+            .atomic()
+            .get(key)
+            .del(key)
+            .ignore()
+            .query_async(&mut self.conn.clone())
+            .await
+            .warn_err()?;
+
+        Ok(value)
+    }
+}
+
+fn client_fields(client: &ClientInfo) -> Vec<(&'static str, String)> {
+    let mut fields = vec![];
+    if let Some(ip) = &client.ip {
+        fields.push(("ip", ip.clone()));
+    }
+    if let Some(user_agent) = &client.user_agent {
+        fields.push(("user_agent", user_agent.clone()));
+    }
+    fields
+}
+
+fn registration_from(mut fields: HashMap<String, String>) -> Option<PendingRegistration> {
+    Some(PendingRegistration {
+        email: fields.remove("email")?,
+        hashed_password: fields.remove("hashed_password")?,
+        username: fields.remove("username")?,
+        display_name: fields.remove("display_name"),
+    })
+}
+
+#[async_trait]
+impl EphemeralStore for RedisStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        self.conn.clone().get(key).await.warn_err()
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<(), Error> {
+        let mut conn = self.conn.clone();
+        match ttl {
+            Some(ttl) => conn.pset_ex(key, value, ttl.as_millis().max(1) as u64).await,
+            None => conn.set(key, value).await,
+        }
+        .warn_err()
+    }
+
+    async fn set_nx(&self, key: &str, value: &str, ttl: Duration) -> Result<bool, Error> {
+        let stored: Option<String> = self
+            .conn
+            .clone()
+            .set_options(
+                key,
+                value,
+                SetOptions::default()
+                    .conditional_set(ExistenceCheck::NX)
+                    .with_expiration(SetExpiry::PX(ttl.as_millis().max(1) as u64)),
+            )
+            .await
+            .warn_err()?;
+
+        Ok(stored.is_some())
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>, Error> {
+        let ttl: i64 = self.conn.clone().pttl(key).await.warn_err()?;
+
+        Ok((ttl > 0).then(|| Duration::from_millis(ttl as u64)))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        self.conn.clone().del(key).await.warn_err()
+    }
+
+    async fn increment(&self, key: &str, ttl: Duration) -> Result<u64, Error> {
+        let (count,): (u64,) = redis::pipe()
+            .atomic()
+            .incr(key, 1)
+            .pexpire(key, ttl.as_millis().max(1) as i64)
+            .ignore()
+            .query_async(&mut self.conn.clone())
+            .await
+            .warn_err()?;
+
+        Ok(count)
+    }
+
+    async fn store_token(
+        &self,
+        purpose: TokenPurpose,
+        token: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        self.set(&format!("{}:{}", purpose.prefix(), token), value, Some(ttl))
+            .await
+    }
+
+    async fn consume_token(
+        &self,
+        purpose: TokenPurpose,
+        token: &str,
+    ) -> Result<Option<String>, Error> {
+        self.take(&format!("{}:{}", purpose.prefix(), token)).await
+    }
+
+    async fn store_authorization_code(
+        &self,
+        code: &str,
+        grant: &AuthorizationCode,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let key = format!("oauth:{}", code);
+
+        redis::pipe()
+            .atomic()
+            .hset_multiple(
+                &key,
+                &[
+                    ("client_id", grant.client_id.to_string()),
+                    ("scopes", grant.scopes.clone()),
+                    ("uid", grant.uid.to_string()),
+                ],
+            )
+            .ignore()
+            .pexpire(&key, ttl.as_millis() as i64)
+            .ignore()
+            .query_async(&mut self.conn.clone())
+            .await
+            .warn_err()
+    }
+
+    async fn consume_authorization_code(
+        &self,
+        code: &str,
+    ) -> Result<Option<AuthorizationCode>, Error> {
+        let key = format!("oauth:{}", code);
+        let (mut fields,): (HashMap<String, String>,) = redis::pipe()
+            .atomic()
+            .hgetall(&key)
+            .del(&key)
+            .ignore()
+            .query_async(&mut self.conn.clone())
+            .await
+            .warn_err()?;
+
+        let parse = |v: Option<String>| v.and_then(|v| v.parse().ok());
+        Ok(
+            match (
+                parse(fields.remove("client_id")),
+                fields.remove("scopes"),
+                parse(fields.remove("uid")),
+            ) {
+                (Some(client_id), Some(scopes), Some(uid)) => Some(AuthorizationCode {
+                    client_id,
+                    scopes,
+                    uid,
+                }),
+                _ => None,
+            },
+        )
+    }
+
+    async fn store_verification(
+        &self,
+        token: &str,
+        registration: &PendingRegistration,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let mut fields = vec![
+            ("email", registration.email.clone()),
+            ("hashed_password", registration.hashed_password.clone()),
+            ("username", registration.username.clone()),
+        ];
+        if let Some(display_name) = &registration.display_name {
+            fields.push(("display_name", display_name.clone()));
+        }
+
+        let mut invocation = STORE_VERIFICATION.prepare_invoke();
+        invocation
+            .key(format!("email_verify_pending:{}", registration.email))
+            .key(format!("email_verify:{}", token))
+            .key(format!("username_pending:{}", registration.username))
+            .arg(token)
+            .arg(ttl.as_millis().max(1) as u64)
+            .arg(&registration.email);
+        for (field, value) in &fields {
+            invocation.arg(*field).arg(value);
+        }
+
+        let _: i64 = invocation
+            .invoke_async(&mut self.conn.clone())
+            .await
+            .warn_err()?;
+        Ok(())
+    }
+
+    async fn verification(&self, token: &str) -> Result<Option<PendingRegistration>, Error> {
+        let fields: HashMap<String, String> = self
+            .conn
+            .clone()
+            .hgetall(format!("email_verify:{}", token))
+            .await
+            .warn_err()?;
+
+        Ok(registration_from(fields))
+    }
+
+    async fn consume_verification(
+        &self,
+        token: &str,
+    ) -> Result<Option<PendingRegistration>, Error> {
+        let key = format!("email_verify:{}", token);
+        let (fields,): (HashMap<String, String>,) = redis::pipe()
+            .atomic()
+            .hgetall(&key)
+            .del(&key)
+            .ignore()
+            .query_async(&mut self.conn.clone())
+            .await
+            .warn_err()?;
+
+        let Some(registration) = registration_from(fields) else {
+            return Ok(None);
+        };
+
+        let pending_key = format!("email_verify_pending:{}", registration.email);
+        if self.get(&pending_key).await?.as_deref() == Some(token) {
+            self.delete(&pending_key).await?;
+        }
+
+        Ok(Some(registration))
+    }
+
+    async fn pending_verification(&self, email: &str) -> Result<Option<String>, Error> {
+        self.get(&format!("email_verify_pending:{}", email)).await
+    }
+
+    async fn username_claim(&self, username: &str) -> Result<Option<String>, Error> {
+        self.get(&format!("username_pending:{}", username)).await
+    }
+
+    async fn release_username_claim(&self, username: &str) -> Result<(), Error> {
+        self.delete(&format!("username_pending:{}", username)).await
+    }
+
+    async fn store_login_code(
+        &self,
+        email: &str,
+        code: &str,
+        uid: i32,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let key = format!("email_login_code:{}", email);
+
+        redis::pipe()
+            .atomic()
+            .del(&key)
+            .ignore()
+            .hset_multiple(
+                &key,
+                &[
+                    ("code", code.to_string()),
+                    ("uid", uid.to_string()),
+                    ("attempts", "0".to_string()),
+                ],
+            )
+            .ignore()
+            .pexpire(&key, ttl.as_millis() as i64)
+            .ignore()
+            .query_async(&mut self.conn.clone())
+            .await
+            .warn_err()
+    }
+
+    async fn check_login_code(
+        &self,
+        email: &str,
+        code: &str,
+        max_attempts: u32,
+    ) -> Result<CodeCheck, Error> {
+        let result: Vec<i32> = CHECK_LOGIN_CODE
+            .key(format!("email_login_code:{}", email))
+            .arg(code)
+            .arg(max_attempts)
+            .invoke_async(&mut self.conn.clone())
+            .await
+            .warn_err()?;
+
+        Ok(match result[..] {
+            [1, uid] => CodeCheck::Correct { uid },
+            [2, uid, attempts] => CodeCheck::Incorrect {
+                uid,
+                attempts: attempts as u32,
+            },
+            _ => CodeCheck::Missing,
+        })
+    }
+
+    async fn create_session(
+        &self,
+        uid: i32,
+        client: &ClientInfo,
+        name: Option<String>,
+    ) -> Result<(String, String), Error> {
+        let session = generate_refresh_token();
+        let token = generate_refresh_token();
+        let now = Utc::now().timestamp().to_string();
+
+        let mut fields = vec![
+            ("uid", uid.to_string()),
+            ("name", name.unwrap_or_else(|| client.device_name())),
+            ("created_at", now.clone()),
+            ("last_used_at", now),
+        ];
+        fields.extend(client_fields(client));
+
+        // One transaction, so that a session is never left behind without an expiry.
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .hset_multiple(format!("session:{}", &session), &fields)
+            .ignore();
+        self.queue_refresh_token(&mut pipe, &token, &session, uid);
+        let _: () = pipe.query_async(&mut self.conn.clone()).await.warn_err()?;
+
+        Ok((session, token))
+    }
+
+    async fn rotate_refresh_token(
+        &self,
+        token: &str,
+        client: &ClientInfo,
+    ) -> Result<Option<(i32, String, String)>, Error> {
+        let Some(session) = self.take(&format!("refresh:{}", token)).await? else {
+            return Ok(None);
+        };
+
+        let mut conn = self.conn.clone();
+        let session_key = format!("session:{}", &session);
+        let uid: Option<i32> = conn.hget(&session_key, "uid").await.warn_err()?;
+        let Some(uid) = uid else {
+            return Ok(None);
+        };
+
+        let mut fields = vec![("last_used_at", Utc::now().timestamp().to_string())];
+        fields.extend(client_fields(client));
+        let _: () = conn.hset_multiple(&session_key, &fields).await.warn_err()?;
+
+        let new_token = generate_refresh_token();
+        self.insert_refresh_token(&new_token, &session, uid).await?;
+
+        Ok(Some((uid, session, new_token)))
+    }
+
+    /// Also drops index entries whose session has expired.
+    async fn list_sessions(
+        &self,
+        uid: i32,
+        current: Option<&str>,
+    ) -> Result<Vec<Session>, Error> {
+        let mut conn = self.conn.clone();
+        let index_key = format!("user_sessions:{}", uid);
+        let ids: Vec<String> = conn.smembers(&index_key).await.warn_err()?;
+        let mut sessions = Vec::with_capacity(ids.len());
+
+        for id in ids {
+            let mut fields: HashMap<String, String> =
+                conn.hgetall(format!("session:{}", &id)).await.warn_err()?;
+
+            if fields.get("uid").and_then(|v| v.parse::<i32>().ok()) != Some(uid) {
+                let _: () = conn.srem(&index_key, &id).await.warn_err()?;
+                continue;
+            }
+
+            let timestamp =
+                |v: Option<String>| v.and_then(|v| v.parse().ok()).unwrap_or_default();
+            sessions.push(Session {
+                current: current == Some(id.as_str()),
+                name: fields.remove("name").unwrap_or_default(),
+                created_at: timestamp(fields.remove("created_at")),
+                last_used_at: timestamp(fields.remove("last_used_at")),
+                ip: fields.remove("ip"),
+                user_agent: fields.remove("user_agent"),
+                id,
+            });
+        }
+
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_used_at));
+        Ok(sessions)
+    }
+
+    async fn revoke_session(&self, uid: i32, session: &str) -> Result<bool, Error> {
+        let mut conn = self.conn.clone();
+        let session_key = format!("session:{}", session);
+        let v: (Option<i32>, Option<String>) = conn
+            .hget(&session_key, &["uid", "refresh_token"])
+            .await
+            .warn_err()?;
+
+        let (Some(owner), refresh_token) = v else {
+            return Ok(false);
+        };
+        if owner != uid {
+            return Ok(false);
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .del(&session_key)
+            .ignore()
+            .srem(format!("user_sessions:{}", uid), session)
+            .ignore();
+        if let Some(refresh_token) = refresh_token {
+            pipe.del(format!("refresh:{}", refresh_token)).ignore();
+        }
+        let _: () = pipe.query_async(&mut conn).await.warn_err()?;
+
+        Ok(true)
+    }
+
+    async fn revoke_all_sessions(&self, uid: i32) -> Result<(), Error> {
+        let ids: Vec<String> = self
+            .conn
+            .clone()
+            .smembers(format!("user_sessions:{}", uid))
+            .await
+            .warn_err()?;
+
+        for id in ids {
+            self.revoke_session(uid, &id).await?;
+        }
+
+        self.delete(&format!("user_sessions:{}", uid)).await
+    }
+
+    async fn hit_rate_limit(&self, key: &str, algorithm: &Algorithm) -> Result<Outcome, Error> {
+        let mut conn = self.conn.clone();
+
+        let result: redis::RedisResult<(u8, i64, u64, u64)> = match *algorithm {
+            Algorithm::TokenBucket {
+                capacity,
+                refill_per_second,
+            } => {
+                TOKEN_BUCKET
+                    .key(key)
+                    .arg(capacity)
+                    .arg(refill_per_second / 1000.0)
+                    .invoke_async(&mut conn)
+                    .await
+            }
+            Algorithm::SlidingWindow { limit, window } => {
+                let member = rand::thread_rng().gen::<u32>();
+                SLIDING_WINDOW
+                    .key(key)
+                    .arg(limit)
+                    .arg(window.as_millis() as u64)
+                    .arg(member)
+                    .invoke_async(&mut conn)
+                    .await
+            }
+        };
+
+        let (allowed, remaining, retry_after_ms, reset_ms) = result.warn_err()?;
+
+        Ok(Outcome {
+            allowed: allowed == 1,
+            remaining: remaining.max(0) as u64,
+            retry_after_ms,
+            reset_ms,
+        })
+    }
+
+    async fn publish_account_event(&self, fields: &[(&str, String)]) -> Result<(), Error> {
+        let _: String = self
+            .conn
+            .clone()
+            .xadd_maxlen(
+                ACCOUNT_EVENTS_STREAM,
+                StreamMaxlen::Approx(10_000),
+                "*",
+                fields,
+            )
+            .await
+            .warn_err()?;
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        let _: String = redis::cmd("PING")
+            .query_async(&mut self.conn.clone())
+            .await
+            .warn_err()?;
+
+        Ok(())
+    }
+}
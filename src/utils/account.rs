@@ -0,0 +1,144 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, EntityTrait, QueryFilter, Set, TransactionTrait,
+};
+use serde_json::json;
+
+use crate::data::error::Error;
+use crate::data::session::ClientInfo;
+use crate::entity::audit_event::{self, EventType};
+use crate::entity::{known_device, suspension, user, username_redirect};
+use crate::utils::audit::{recorded_by_email, AuditEvent};
+use crate::utils::db::StanderizeError;
+use crate::AppState;
+
+/// Cancels the pending deletion of `user`, if there is one.
+pub async fn cancel_deletion(
+    state: &AppState,
+    user: &user::Model,
+    client: &ClientInfo,
+) -> Result<(), Error> {
+    if user.deletion_scheduled_at.is_none() {
+        return Ok(());
+    }
+
+    let mut active: user::ActiveModel = user.clone().into();
+    active.deletion_scheduled_at = Set(None);
+    active.update(&state.db).await.warn_err()?;
+
+    AuditEvent::new(EventType::AccountDeletionCancelled)
+        .user(user.id)
+        .client(client)
+        .record(&state.db)
+        .await;
+
+    Ok(())
+}
+
+/// Periodically deletes the accounts whose grace period has passed.
+pub async fn purge_deleted_accounts(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(
+        state.config.accounts.purge_interval_seconds,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        let due = match user::Entity::find()
+            .filter(user::Column::DeletionScheduledAt.lte(Utc::now().naive_utc()))
+            .all(&state.db)
+            .await
+            .warn_err()
+        {
+            Ok(due) => due,
+            Err(_) => continue,
+        };
+
+        for user in due {
+            if delete_account(&state, &user).await.is_ok() {
+                tracing::info!("deleted account {}", user.id);
+            }
+        }
+    }
+}
+
+/// Deletes `user` with everything tied to them and announces it to connected services,
+/// so that they can purge the data they hold about the user.
+///
+/// Audit events about the user are kept for security purposes, but are stripped of
+/// their IP, user agent and details. So are those recorded by email only, such as failed
+/// logins and registrations that predate the account.
+async fn delete_account(state: &AppState, user: &user::Model) -> Result<(), Error> {
+    state.store.revoke_all_sessions(user.id).await?;
+
+    let txn = state.db.begin().await.warn_err()?;
+
+    known_device::Entity::delete_many()
+        .filter(known_device::Column::UserId.eq(user.id))
+        .exec(&txn)
+        .await
+        .warn_err()?;
+
+    username_redirect::Entity::delete_many()
+        .filter(username_redirect::Column::UserId.eq(user.id))
+        .exec(&txn)
+        .await
+        .warn_err()?;
+
+    suspension::Entity::delete_many()
+        .filter(suspension::Column::UserId.eq(user.id))
+        .exec(&txn)
+        .await
+        .warn_err()?;
+
+    audit_event::Entity::update_many()
+        .col_expr(audit_event::Column::Ip, Expr::value(Option::<String>::None))
+        .col_expr(
+            audit_event::Column::UserAgent,
+            Expr::value(Option::<String>::None),
+        )
+        .col_expr(audit_event::Column::Details, Expr::value(json!({})))
+        .filter(
+            Condition::any()
+                .add(audit_event::Column::ActorId.eq(user.id))
+                .add(audit_event::Column::SubjectId.eq(user.id))
+                .add(recorded_by_email(&user.email)),
+        )
+        .exec(&txn)
+        .await
+        .warn_err()?;
+
+    user::Entity::delete_by_id(user.id)
+        .exec(&txn)
+        .await
+        .warn_err()?;
+
+    txn.commit().await.warn_err()?;
+
+    if state
+        .storage
+        .delete_prefix(&format!("avatars/{}/", user.id))
+        .await
+        .is_err()
+    {
+        tracing::warn!("failed to delete avatars of user {}", user.id);
+    }
+
+    AuditEvent::new(EventType::AccountDeleted)
+        .subject(user.id)
+        .record(&state.db)
+        .await;
+
+    state
+        .store
+        .publish_account_event(&[
+            ("type", "account_deleted".to_string()),
+            ("uid", user.id.to_string()),
+            ("deleted_at", Utc::now().timestamp().to_string()),
+        ])
+        .await
+}
@@ -0,0 +1,99 @@
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveValue, Condition, DatabaseConnection, EntityTrait};
+use serde_json::Value;
+
+use crate::data::session::ClientInfo;
+use crate::entity::audit_event::{self, EventType};
+
+/// An entry for the security audit log, written with [`AuditEvent::record`].
+#[derive(Debug)]
+pub struct AuditEvent {
+    event_type: EventType,
+    actor_id: Option<i32>,
+    subject_id: Option<i32>,
+    client: ClientInfo,
+    client_id: Option<i32>,
+    details: Value,
+}
+
+impl AuditEvent {
+    pub fn new(event_type: EventType) -> Self {
+        Self {
+            event_type,
+            actor_id: None,
+            subject_id: None,
+            client: ClientInfo::default(),
+            client_id: None,
+            details: Value::Object(Default::default()),
+        }
+    }
+
+    /// Sets the user who acted on their own account, as both actor and subject.
+    pub fn user(self, uid: i32) -> Self {
+        self.actor(uid).subject(uid)
+    }
+
+    pub fn actor(mut self, uid: i32) -> Self {
+        self.actor_id = Some(uid);
+        self
+    }
+
+    pub fn subject(mut self, uid: i32) -> Self {
+        self.subject_id = Some(uid);
+        self
+    }
+
+    pub fn client(mut self, client: &ClientInfo) -> Self {
+        self.client = client.clone();
+        self
+    }
+
+    pub fn client_id(mut self, client_id: i32) -> Self {
+        self.client_id = Some(client_id);
+        self
+    }
+
+    pub fn details(mut self, details: Value) -> Self {
+        self.details = details;
+        self
+    }
+
+    /// Appends the event to the audit log.
+    ///
+    /// Failures are logged rather than returned so that auditing never fails the
+    /// request being audited.
+    pub async fn record(self, db: &DatabaseConnection) {
+        let event_type = self.event_type;
+        let result = audit_event::Entity::insert(audit_event::ActiveModel {
+            id: ActiveValue::NotSet,
+            actor_id: ActiveValue::Set(self.actor_id),
+            subject_id: ActiveValue::Set(self.subject_id),
+            event_type: ActiveValue::Set(self.event_type),
+            ip: ActiveValue::Set(self.client.ip),
+            user_agent: ActiveValue::Set(self.client.user_agent),
+            client_id: ActiveValue::Set(self.client_id),
+            details: ActiveValue::Set(self.details),
+            created_at: ActiveValue::NotSet,
+        })
+        .exec(db)
+        .await;
+
+        if let Err(e) = result {
+            tracing::warn!("failed to record audit event {:?}: {}", event_type, e);
+        }
+    }
+}
+
+/// Matches the events recorded by `email` rather than by user id, such as failed logins
+/// and registrations that predate the account.
+pub fn recorded_by_email(email: &str) -> Condition {
+    Condition::any()
+        .add(Expr::cust_with_values(
+            "lower(details->>'email') = lower($1)",
+            [email],
+        ))
+        .add(Expr::cust_with_values(
+            "lower(details->'locked'->>'email') = lower($1)",
+            [email],
+        ))
+}
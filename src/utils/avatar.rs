@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::Cursor;
+
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::data::error::Error;
+
+/// Square sizes every avatar is rendered in, in pixels.
+pub const SIZES: [u32; 4] = [64, 128, 256, 512];
+
+const MAX_DIMENSION: u32 = 4096;
+
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct AvatarUrls {
+    png: String,
+    webp: Option<String>,
+}
+
+/// The URLs of an avatar, by size. Users without one get their Gravatar, falling back to
+/// an identicon, or just an identicon when shown to others.
+#[derive(Serialize, ToSchema, Debug, Clone)]
+pub struct Avatar {
+    custom: bool,
+    sizes: BTreeMap<u32, AvatarUrls>,
+}
+
+impl Avatar {
+    /// The avatar shown to the user themselves. `base` is the URL the sizes of an uploaded
+    /// avatar were stored under.
+    pub fn new(base: Option<&str>, email: &str) -> Self {
+        match base {
+            Some(base) => Self::uploaded(base),
+            None => Self::gravatar(&email.trim().to_lowercase(), ""),
+        }
+    }
+
+    /// The avatar shown to everyone else. Without an upload this is an identicon of the
+    /// user id, since a Gravatar URL would give away a hash of the email.
+    pub fn public(base: Option<&str>, uid: i32) -> Self {
+        match base {
+            Some(base) => Self::uploaded(base),
+            None => Self::gravatar(&format!("websxz-user-{}", uid), "&f=y"),
+        }
+    }
+
+    fn uploaded(base: &str) -> Self {
+        Avatar {
+            custom: true,
+            sizes: SIZES
+                .iter()
+                .map(|&size| {
+                    (
+                        size,
+                        AvatarUrls {
+                            png: format!("{}/{}.png", base, size),
+                            webp: Some(format!("{}/{}.webp", base, size)),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// `options` are appended to the query, such as `&f=y` to force the identicon.
+    fn gravatar(identity: &str, options: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(identity);
+        let mut hash = String::new();
+        for byte in hasher.finalize() {
+            write!(&mut hash, "{:02x}", byte).unwrap();
+        }
+
+        Avatar {
+            custom: false,
+            sizes: SIZES
+                .iter()
+                .map(|&size| {
+                    (
+                        size,
+                        AvatarUrls {
+                            png: format!(
+                                "https://www.gravatar.com/avatar/{}?s={}&d=identicon{}",
+                                hash, size, options
+                            ),
+                            webp: None,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A rendered avatar file, named by its size and extension.
+pub struct Rendition {
+    pub name: String,
+    pub content_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// Decodes an uploaded PNG, JPEG, WebP or GIF image and renders it in every size.
+///
+/// The image is rotated according to its EXIF orientation and center-cropped to a square.
+/// The renditions are encoded from raw pixels, so no metadata of the upload survives.
+pub fn process(data: &[u8]) -> Result<Vec<Rendition>, Error> {
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|_| Error::UnsupportedImage)?;
+    if !matches!(
+        reader.format(),
+        Some(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Gif)
+    ) {
+        return Err(Error::UnsupportedImage);
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder().map_err(|_| Error::UnsupportedImage)?;
+    let orientation = decoder.orientation().map_err(|_| Error::UnsupportedImage)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|_| Error::UnsupportedImage)?;
+    image.apply_orientation(orientation);
+
+    let side = image.width().min(image.height());
+    let image = image
+        .crop_imm(
+            (image.width() - side) / 2,
+            (image.height() - side) / 2,
+            side,
+            side,
+        )
+        .into_rgba8();
+    let image = DynamicImage::ImageRgba8(image);
+
+    let mut renditions = Vec::new();
+    for size in SIZES {
+        let resized = image.resize_exact(size, size, FilterType::Lanczos3);
+
+        let mut png = Vec::new();
+        resized
+            .write_with_encoder(PngEncoder::new(&mut png))
+            .map_err(|e| {
+                tracing::warn!("failed to encode avatar: {}", e);
+                Error::InternalServerError
+            })?;
+        renditions.push(Rendition {
+            name: format!("{}.png", size),
+            content_type: "image/png",
+            data: png,
+        });
+
+        let mut webp = Vec::new();
+        resized
+            .write_with_encoder(WebPEncoder::new_lossless(&mut webp))
+            .map_err(|e| {
+                tracing::warn!("failed to encode avatar: {}", e);
+                Error::InternalServerError
+            })?;
+        renditions.push(Rendition {
+            name: format!("{}.webp", size),
+            content_type: "image/webp",
+            data: webp,
+        });
+    }
+
+    Ok(renditions)
+}
@@ -1,43 +1,54 @@
+use crate::config::CaptchaConfig;
 use crate::data::error::Error;
+use crate::utils::metrics::CAPTCHA_FAILURES;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::env;
 use std::fmt::Debug;
 use std::ops::Deref;
+use utoipa::ToSchema;
 
 lazy_static! {
     static ref TURNSTILE_URL: &'static str =
         "https://challenges.cloudflare.com/turnstile/v0/siteverify";
-    static ref TURNSTILE_SECRET_KEY: &'static str = env::var("TURNSTILE_SECRET_KEY")
-        .expect("TURNSTILE_SECRET_KEY env not found")
-        .leak();
     static ref REQUEST_CLIENT: reqwest::Client = reqwest::Client::new();
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(tag = "type", content = "content")]
 pub enum Captcha {
     #[serde(rename = "turnstile")]
     Turnstile(String),
 }
 
-pub async fn verify_captcha(captcha: Captcha, remote_ip: Option<&str>) -> Result<(), Error> {
+pub async fn verify_captcha(
+    config: &CaptchaConfig,
+    captcha: Captcha,
+    remote_ip: Option<&str>,
+) -> Result<(), Error> {
     if cfg!(debug_assertions) {
         return Ok(())
     }
 
-    match captcha {
-        Captcha::Turnstile(token) => verify_turnstile(token.as_str(), remote_ip).await,
+    let result = match captcha {
+        Captcha::Turnstile(token) => {
+            let secret = config.turnstile_secret.as_deref().unwrap_or_default();
+            verify_turnstile(secret, token.as_str(), remote_ip).await
+        }
+    };
+    if result.is_err() {
+        CAPTCHA_FAILURES.inc();
     }
+    result
 }
 
-async fn verify_turnstile(token: &str, remote_ip: Option<&str>) -> Result<(), Error> {
+#[tracing::instrument(name = "turnstile", skip_all, fields(otel.kind = "client"))]
+async fn verify_turnstile(secret: &str, token: &str, remote_ip: Option<&str>) -> Result<(), Error> {
     let resp = REQUEST_CLIENT
         .post(*TURNSTILE_URL)
         .body(
             json!({
-                "secret": *TURNSTILE_URL,
+                "secret": secret,
                 "response": token,
                 "remoteip": remote_ip,
             })
@@ -64,7 +75,7 @@ async fn verify_turnstile(token: &str, remote_ip: Option<&str>) -> Result<(), Er
     if resp_data.success {
         Ok(())
     } else {
-        for c in resp_data.error_codes {
+        if let Some(c) = resp_data.error_codes.first() {
             return Err(match c.as_str() {
                 "missing-input-response" => Error::MissingCaptchaToken,
                 "invalid-input-response" => Error::InvalidCaptcha,
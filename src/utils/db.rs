@@ -1,3 +1,6 @@
+use std::fmt;
+use std::panic::Location;
+
 use crate::data::error::Error;
 
 pub trait StanderizeError<V> {
@@ -5,27 +8,46 @@ pub trait StanderizeError<V> {
     fn debug_err(self) -> Result<V, Error>;
 }
 
+/// Both log the whole chain of causes along with where the error was caught, as the
+/// response only says that something went wrong.
 impl<V, E> StanderizeError<V> for Result<V, E>
 where
     E: std::error::Error,
 {
+    #[track_caller]
     fn warn_err(self) -> Result<V, Error> {
         match self {
             Ok(v) => Ok(v),
             Err(e) => {
-                tracing::warn!("{}", e);
+                tracing::warn!(at = %Location::caller(), "{}", Chain(&e));
                 Err(Error::InternalServerError)
             }
         }
     }
 
+    #[track_caller]
     fn debug_err(self) -> Result<V, Error> {
         match self {
             Ok(v) => Ok(v),
             Err(e) => {
-                tracing::debug!("{}", e);
+                tracing::debug!(at = %Location::caller(), "{}", Chain(&e));
                 Err(Error::InternalServerError)
             }
         }
     }
 }
+
+/// Displays an error followed by its sources, as `error: cause: cause`.
+pub struct Chain<'a>(pub &'a dyn std::error::Error);
+
+impl fmt::Display for Chain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(cause) = source {
+            write!(f, ": {}", cause)?;
+            source = cause.source();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use askama::Template;
+use chrono::Utc;
+use lettre::message::{header, SinglePart};
+use lettre::Message;
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, QueryFilter, Set};
+use sha2::{Digest, Sha256};
+
+use crate::data::error::Error;
+use crate::data::session::ClientInfo;
+use crate::entity::{known_device, user};
+use crate::store::{generate_refresh_token, TokenPurpose};
+use crate::utils::db::StanderizeError;
+use crate::AppState;
+
+#[derive(Template)]
+#[template(path = "new_sign_in.html")]
+struct NewSignInTemplate<'a> {
+    name: &'a str,
+    time: &'a str,
+    location: Option<&'a str>,
+    ip: Option<&'a str>,
+    device: &'a str,
+    revoke_link: &'a str,
+}
+
+fn fingerprint(client: &ClientInfo) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(client.user_agent.as_deref().unwrap_or_default());
+
+    let mut result = String::new();
+    for byte in hasher.finalize() {
+        write!(&mut result, "{:02x}", byte).unwrap();
+    }
+    result
+}
+
+/// The /24 (IPv4) or /48 (IPv6) network of the client.
+fn ip_range(ip: Option<IpAddr>) -> String {
+    match ip {
+        Some(IpAddr::V4(ip)) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        Some(IpAddr::V6(ip)) => {
+            let s = ip.segments();
+            format!("{:x}:{:x}:{:x}::/48", s[0], s[1], s[2])
+        }
+        None => "unknown".to_string(),
+    }
+}
+
+/// Records the device `user` just signed in from and, if the device or its network has
+/// not been seen on this account before, emails an alert with a link that revokes
+/// `session`.
+///
+/// The very first sign-in of an account is not alerted on.
+pub async fn check_new_device(
+    state: &AppState,
+    user: &user::Model,
+    client: &ClientInfo,
+    session: &str,
+) -> Result<(), Error> {
+    let ip: Option<IpAddr> = client.ip.as_deref().and_then(|ip| ip.parse().ok());
+    let fingerprint = fingerprint(client);
+    let ip_range = ip_range(ip);
+    let now = Utc::now().naive_utc();
+    let db = &state.db;
+
+    let known = known_device::Entity::find()
+        .filter(known_device::Column::UserId.eq(user.id))
+        .all(db)
+        .await
+        .warn_err()?;
+
+    let device_known = known.iter().any(|d| d.fingerprint == fingerprint);
+    let range_known = known.iter().any(|d| d.ip_range == ip_range);
+
+    match known
+        .iter()
+        .find(|d| d.fingerprint == fingerprint && d.ip_range == ip_range)
+    {
+        Some(device) => {
+            let mut device: known_device::ActiveModel = device.clone().into();
+            device.last_seen_at = Set(now);
+            device.update(db).await.warn_err()?;
+        }
+        None => {
+            known_device::Entity::insert(known_device::ActiveModel {
+                id: ActiveValue::NotSet,
+                user_id: Set(user.id),
+                fingerprint: Set(fingerprint),
+                ip_range: Set(ip_range),
+                first_seen_at: Set(now),
+                last_seen_at: Set(now),
+            })
+            .exec(db)
+            .await
+            .warn_err()?;
+        }
+    }
+
+    if known.is_empty() || (device_known && range_known) {
+        return Ok(());
+    }
+
+    let token = generate_refresh_token();
+    state
+        .store
+        .store_token(
+            TokenPurpose::SessionRevoke,
+            &token,
+            &format!("{}:{}", user.id, session),
+            Duration::from_secs(state.config.tokens.session_revoke_link_ttl_seconds),
+        )
+        .await?;
+
+    let location = ip.and_then(|ip| state.geoip.locate(ip));
+    let message = Message::builder()
+        .subject("你的WebSxz账号在新设备上登录")
+        .from(state.mailer.from.clone())
+        .to(user.email.parse().map_err(|e| {
+            tracing::warn!("stored email illegal: {}", e);
+            Error::InternalServerError
+        })?)
+        .singlepart(
+            SinglePart::builder()
+                .header(header::ContentType::TEXT_HTML)
+                .body(
+                    NewSignInTemplate {
+                        name: &user.name,
+                        time: &now.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+                        location: location.as_deref(),
+                        ip: client.ip.as_deref(),
+                        device: &client.device_name(),
+                        revoke_link: &state
+                            .config
+                            .server
+                            .link(&format!("/sessions/revoke?token={}", token)),
+                    }
+                    .render()
+                    .map_err(|e| {
+                        tracing::warn!("email template render failed: {}", e);
+                        Error::InternalServerError
+                    })?,
+                ),
+        )
+        .map_err(|e| {
+            tracing::warn!("failed to build email: {}", e);
+            Error::InternalServerError
+        })?;
+
+    state.mailer.send(message).await
+}
@@ -1,26 +1,68 @@
+use crate::config::SmtpConfig;
 use crate::data::error::Error;
-use lazy_static::lazy_static;
+use crate::utils::db::StanderizeError;
+use crate::utils::metrics::EMAILS;
+use lettre::message::Mailbox;
 use lettre::transport::smtp::PoolConfig;
 use lettre::{Message, SmtpTransport, Transport};
-use std::env;
 
-lazy_static! {
-    static ref SENDER: SmtpTransport =
-        SmtpTransport::from_url(&env::var("SMTP_URL").expect("SMTP_URL must be set"))
-            .expect("failed to parse smtp url")
-            .pool_config(PoolConfig::new().max_size(20))
-            .build();
+/// Sends emails through the configured SMTP server.
+pub struct Mailer {
+    sender: Option<SmtpTransport>,
+    pub from: Mailbox,
 }
 
-pub fn send(message: &Message) -> Result<(), Error> {
-    if cfg!(debug_assertions) {
-        tracing::debug!("{}", String::from_utf8(message.formatted()).unwrap());
-        return Ok(());
+impl Mailer {
+    /// Expects `config` to have been validated.
+    pub fn new(config: &SmtpConfig) -> Self {
+        Mailer {
+            sender: config.url.as_deref().map(|url| {
+                SmtpTransport::from_url(url)
+                    .expect("failed to parse smtp url")
+                    .pool_config(PoolConfig::new().max_size(20))
+                    .build()
+            }),
+            from: config.from.parse().expect("parse from mailbox failed"),
+        }
     }
 
-    let _ = SENDER.send(message).map_err(|e| {
-        tracing::warn!("failed to send email: {}", e);
-        Error::InternalServerError
-    });
-    Ok(())
+    /// Sends on the blocking pool, as the SMTP transport is synchronous. Failures to
+    /// deliver are only logged.
+    #[tracing::instrument(name = "smtp send", skip_all, fields(otel.kind = "client"))]
+    pub async fn send(&self, message: Message) -> Result<(), Error> {
+        let Some(sender) = self.sender.clone().filter(|_| !cfg!(debug_assertions)) else {
+            tracing::debug!("{}", String::from_utf8(message.formatted()).unwrap());
+            return Ok(());
+        };
+
+        let sent = tokio::task::spawn_blocking(move || sender.send(&message))
+            .await
+            .warn_err()?;
+        match sent {
+            Ok(_) => EMAILS.with_label_values(&["sent"]).inc(),
+            Err(e) => {
+                tracing::warn!("failed to send email: {}", e);
+                EMAILS.with_label_values(&["failed"]).inc();
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the SMTP server accepts connections. Succeeds without a server, as
+    /// emails are then only logged.
+    pub async fn ping(&self) -> Result<(), Error> {
+        let Some(sender) = self.sender.clone() else {
+            return Ok(());
+        };
+
+        match tokio::task::spawn_blocking(move || sender.test_connection()).await {
+            Ok(Ok(true)) => Ok(()),
+            Ok(Ok(false)) => {
+                tracing::warn!("smtp server did not accept the connection");
+                Err(Error::InternalServerError)
+            }
+            Ok(Err(e)) => Err(e).warn_err(),
+            Err(e) => Err(e).warn_err(),
+        }
+    }
 }
@@ -0,0 +1,46 @@
+use std::net::IpAddr;
+use std::path::Path;
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+/// Locates IPs with a MaxMind City database, if one is configured.
+pub struct GeoIp {
+    reader: Option<Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    pub fn open(path: Option<&Path>) -> Result<Self, MaxMindDBError> {
+        Ok(GeoIp {
+            reader: path.map(Reader::open_readfile).transpose()?,
+        })
+    }
+
+    /// Approximate location of `ip` such as "上海, 中国".
+    pub fn locate(&self, ip: IpAddr) -> Option<String> {
+        let city: geoip2::City = self
+            .reader
+            .as_ref()?
+            .lookup(ip)
+            .map_err(|e| tracing::debug!("GeoIP lookup of {} failed: {}", ip, e))
+            .ok()?;
+
+        let name = |names: Option<std::collections::BTreeMap<&str, &str>>| {
+            names.and_then(|names| {
+                names
+                    .get("zh-CN")
+                    .or_else(|| names.get("en"))
+                    .map(ToString::to_string)
+            })
+        };
+
+        let parts: Vec<String> = [
+            city.city.and_then(|c| name(c.names)),
+            city.country.and_then(|c| name(c.names)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use crate::config::LockoutConfig;
+use crate::data::error::Error;
+use crate::store::EphemeralStore;
+
+const MAX_LOCKOUT: u64 = 24 * 60 * 60;
+const FAILURE_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Something failed logins are counted against.
+#[derive(Debug, Clone, Copy)]
+pub enum LoginSubject<'a> {
+    Account(&'a str),
+    Ip(&'a str),
+}
+
+impl LoginSubject<'_> {
+    fn key(&self) -> String {
+        match self {
+            LoginSubject::Account(email) => format!("account:{}", email.to_lowercase()),
+            LoginSubject::Ip(ip) => format!("ip:{}", ip),
+        }
+    }
+
+    fn threshold(&self, config: &LockoutConfig) -> u32 {
+        match self {
+            LoginSubject::Account(_) => config.threshold,
+            LoginSubject::Ip(_) => config.ip_threshold,
+        }
+    }
+}
+
+/// Fails with [`Error::TooManyAttempts`] if any of the subjects is locked out.
+pub async fn check_lockout(
+    store: &dyn EphemeralStore,
+    subjects: &[LoginSubject<'_>],
+) -> Result<(), Error> {
+    let mut retry_after = Duration::ZERO;
+
+    for subject in subjects {
+        if let Some(ttl) = store.ttl(&format!("login_lock:{}", subject.key())).await? {
+            retry_after = retry_after.max(ttl);
+        }
+    }
+
+    if !retry_after.is_zero() {
+        return Err(Error::TooManyAttempts {
+            retry_after: retry_after.as_secs().max(1),
+        });
+    }
+
+    Ok(())
+}
+
+/// Counts a failed login against `subject`.
+///
+/// Once the threshold is reached the subject is locked out, for twice as long with every
+/// further failure. Returns the lockout duration in seconds if a lockout was started.
+pub async fn record_failure(
+    store: &dyn EphemeralStore,
+    config: &LockoutConfig,
+    subject: LoginSubject<'_>,
+) -> Result<Option<u64>, Error> {
+    let failures = store
+        .increment(&format!("login_failures:{}", subject.key()), FAILURE_WINDOW)
+        .await? as u32;
+    let threshold = subject.threshold(config);
+
+    if failures < threshold {
+        return Ok(None);
+    }
+
+    let exponent = (failures - threshold).min(16);
+    let duration = (config.base_seconds << exponent).min(MAX_LOCKOUT);
+    store
+        .set(
+            &format!("login_lock:{}", subject.key()),
+            &failures.to_string(),
+            Some(Duration::from_secs(duration)),
+        )
+        .await?;
+
+    tracing::info!("{:?} locked out for {}s", subject, duration);
+
+    Ok(Some(duration))
+}
+
+/// Clears the failure counter and any lockout of `subject`.
+pub async fn reset(store: &dyn EphemeralStore, subject: LoginSubject<'_>) -> Result<(), Error> {
+    store.delete(&format!("login_failures:{}", subject.key())).await?;
+    store.delete(&format!("login_lock:{}", subject.key())).await
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+
+use crate::AppState;
+
+lazy_static! {
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "http_requests_total",
+        "HTTP requests by route and status.",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Time taken to answer HTTP requests, by route and status.",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    /// By `method` (`password`, `email_link` or `email_code`) and `outcome`.
+    pub static ref LOGINS: IntCounterVec = register_int_counter_vec!(
+        "logins_total",
+        "Sign-in attempts by method and outcome.",
+        &["method", "outcome"]
+    )
+    .unwrap();
+    pub static ref REGISTRATIONS: IntCounter = register_int_counter!(
+        "registrations_total",
+        "Registrations requested, before their email is verified."
+    )
+    .unwrap();
+    /// By `outcome`: `verified` or `invalid`.
+    pub static ref VERIFICATIONS: IntCounterVec = register_int_counter_vec!(
+        "email_verifications_total",
+        "Email verifications of pending registrations by outcome.",
+        &["outcome"]
+    )
+    .unwrap();
+    /// By `grant`: `password`, `email_login`, `refresh_token` or `authorization_code`.
+    pub static ref TOKENS_ISSUED: IntCounterVec = register_int_counter_vec!(
+        "tokens_issued_total",
+        "Access tokens issued by grant type.",
+        &["grant"]
+    )
+    .unwrap();
+    pub static ref CAPTCHA_FAILURES: IntCounter = register_int_counter!(
+        "captcha_failures_total",
+        "Requests rejected for a missing or invalid captcha."
+    )
+    .unwrap();
+    /// By `outcome`: `rotated` or `rejected`.
+    pub static ref REFRESH_ROTATIONS: IntCounterVec = register_int_counter_vec!(
+        "refresh_token_rotations_total",
+        "Refresh token exchanges by outcome.",
+        &["outcome"]
+    )
+    .unwrap();
+    /// By `outcome`: `sent` or `failed`.
+    pub static ref EMAILS: IntCounterVec = register_int_counter_vec!(
+        "emails_total",
+        "Emails by outcome.",
+        &["outcome"]
+    )
+    .unwrap();
+    static ref DB_CONNECTIONS: IntGauge = register_int_gauge!(
+        "db_pool_connections",
+        "Connections open in the database pool."
+    )
+    .unwrap();
+    static ref DB_IDLE_CONNECTIONS: IntGauge = register_int_gauge!(
+        "db_pool_idle_connections",
+        "Idle connections in the database pool."
+    )
+    .unwrap();
+    static ref STORE_UP: IntGauge = register_int_gauge!(
+        "ephemeral_store_up",
+        "Whether the ephemeral store answered a ping during the last scrape."
+    )
+    .unwrap();
+}
+
+/// Samples the gauges and renders every metric in the Prometheus text format.
+pub async fn render(state: &AppState) -> String {
+    let pool = state.db.get_postgres_connection_pool();
+    DB_CONNECTIONS.set(pool.size() as i64);
+    DB_IDLE_CONNECTIONS.set(pool.num_idle() as i64);
+    let timeout = Duration::from_millis(state.config.health.timeout_ms);
+    let store_up = tokio::time::timeout(timeout, state.store.ping()).await;
+    STORE_UP.set(matches!(store_up, Ok(Ok(()))) as i64);
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        tracing::warn!("failed to encode metrics: {}", e);
+    }
+
+    String::from_utf8(buffer).unwrap_or_default()
+}
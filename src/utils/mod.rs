@@ -1,5 +1,15 @@
+pub mod account;
+pub mod audit;
+pub mod avatar;
 pub mod captcha;
 pub mod email;
 pub mod encryption;
-pub mod redis;
+pub mod geoip;
+pub mod lockout;
+pub mod metrics;
+pub mod storage;
+pub mod suspension;
+pub mod telemetry;
+pub mod username;
 pub mod db;
+pub mod device;
@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::async_trait;
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+
+use crate::config::{AvatarStorage, AvatarsConfig, ConfigError, S3Config};
+use crate::data::error::Error;
+use crate::utils::db::StanderizeError;
+
+/// Somewhere public files such as avatars are kept.
+#[async_trait]
+pub trait ObjectStorage: Send + Sync {
+    async fn put(&self, key: &str, content_type: &str, data: Vec<u8>) -> Result<(), Error>;
+
+    /// Removes every object whose key starts with `prefix`.
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), Error>;
+
+    /// The public URL the object at `key` is served from.
+    fn url(&self, key: &str) -> String;
+}
+
+/// Sets up the configured backend.
+pub fn from_config(config: &AvatarsConfig) -> Result<Arc<dyn ObjectStorage>, ConfigError> {
+    Ok(match config.storage {
+        AvatarStorage::S3 => Arc::new(S3Storage::new(&config.s3)?),
+        AvatarStorage::Local => Arc::new(LocalStorage::new(config)),
+    })
+}
+
+/// Stores objects on the local filesystem, to be served by the application itself.
+///
+/// The directory and the URL it is served at only hold avatars, so the `avatars/` that
+/// keys start with is left out of both.
+pub struct LocalStorage {
+    pub root: PathBuf,
+    base_url: String,
+}
+
+impl LocalStorage {
+    pub fn new(config: &AvatarsConfig) -> Self {
+        LocalStorage {
+            root: config.local_dir.clone(),
+            base_url: config.base_url.clone(),
+        }
+    }
+
+    /// Keys never come from users, but refuse to leave the root all the same.
+    fn path(&self, key: &str) -> Result<PathBuf, Error> {
+        let key = relative(key);
+        if key.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(Error::BadRequest);
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+fn relative(key: &str) -> &str {
+    key.strip_prefix("avatars/").unwrap_or(key)
+}
+
+#[async_trait]
+impl ObjectStorage for LocalStorage {
+    async fn put(&self, key: &str, _content_type: &str, data: Vec<u8>) -> Result<(), Error> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.warn_err()?;
+        }
+        tokio::fs::write(path, data).await.warn_err()
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), Error> {
+        match tokio::fs::remove_dir_all(self.path(prefix.trim_end_matches('/'))?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).warn_err(),
+            _ => Ok(()),
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url.trim_end_matches('/'), relative(key))
+    }
+}
+
+/// Stores objects in an S3-compatible bucket.
+pub struct S3Storage {
+    bucket: Box<Bucket>,
+    public_url: String,
+}
+
+impl S3Storage {
+    pub fn new(config: &S3Config) -> Result<Self, ConfigError> {
+        let invalid = |problem: String| ConfigError::Invalid(vec![problem]);
+        let region = match &config.endpoint {
+            Some(endpoint) => Region::Custom {
+                region: config.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => config
+                .region
+                .parse()
+                .map_err(|e| invalid(format!("avatars.s3.region (S3_REGION) illegal: {}", e)))?,
+        };
+        let public_url = config
+            .public_url
+            .clone()
+            .unwrap_or_else(|| format!("{}/{}", region.endpoint(), config.bucket));
+
+        let credentials = Credentials::from_env()
+            .map_err(|e| invalid(format!("S3 credentials must be set: {}", e)))?;
+        let bucket = Bucket::new(&config.bucket, region, credentials)
+            .map_err(|e| invalid(format!("failed to open S3 bucket {}: {}", config.bucket, e)))?;
+
+        Ok(S3Storage {
+            bucket: if config.path_style {
+                bucket.with_path_style()
+            } else {
+                bucket
+            },
+            public_url,
+        })
+    }
+}
+
+#[async_trait]
+impl ObjectStorage for S3Storage {
+    async fn put(&self, key: &str, content_type: &str, data: Vec<u8>) -> Result<(), Error> {
+        self.bucket
+            .put_object_with_content_type(key, &data, content_type)
+            .await
+            .warn_err()?;
+        Ok(())
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), Error> {
+        let pages = self
+            .bucket
+            .list(prefix.to_string(), None)
+            .await
+            .warn_err()?;
+        for object in pages.into_iter().flat_map(|page| page.contents) {
+            self.bucket.delete_object(&object.key).await.warn_err()?;
+        }
+        Ok(())
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url.trim_end_matches('/'), key)
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::data::error::Error;
+use crate::entity::audit_event::EventType;
+use crate::entity::suspension;
+use crate::store::EphemeralStore;
+use crate::utils::audit::AuditEvent;
+use crate::utils::db::StanderizeError;
+use crate::AppState;
+
+/// What is cached in the ephemeral store under `suspended:{uid}` while a suspension is in
+/// effect, so that every authenticated request can be checked cheaply.
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedSuspension {
+    reason: String,
+    until: Option<NaiveDateTime>,
+}
+
+impl From<&suspension::Model> for Error {
+    fn from(suspension: &suspension::Model) -> Self {
+        Error::AccountSuspended {
+            reason: suspension.reason.clone(),
+            until: suspension.ends_at,
+        }
+    }
+}
+
+/// The suspension currently in effect for `uid`, if any. Of several, the one ending
+/// last is returned.
+pub async fn active_suspension(
+    db: &DatabaseConnection,
+    uid: i32,
+) -> Result<Option<suspension::Model>, Error> {
+    let now = Utc::now().naive_utc();
+
+    let active = suspension::Entity::find()
+        .filter(suspension::Column::UserId.eq(uid))
+        .filter(suspension::Column::LiftedAt.is_null())
+        .order_by_desc(suspension::Column::Id)
+        .all(db)
+        .await
+        .warn_err()?
+        .into_iter()
+        .filter(|suspension| suspension.is_active(now));
+
+    Ok(active.max_by_key(|suspension| suspension.ends_at.unwrap_or(NaiveDateTime::MAX)))
+}
+
+/// Those of `uids` that are currently suspended.
+pub async fn suspended_among(db: &DatabaseConnection, uids: &[i32]) -> Result<HashSet<i32>, Error> {
+    let now = Utc::now().naive_utc();
+
+    Ok(suspension::Entity::find()
+        .filter(suspension::Column::UserId.is_in(uids.iter().copied()))
+        .filter(suspension::Column::LiftedAt.is_null())
+        .all(db)
+        .await
+        .warn_err()?
+        .into_iter()
+        .filter(|suspension| suspension.is_active(now))
+        .map(|suspension| suspension.user_id)
+        .collect())
+}
+
+/// Fails with [`Error::AccountSuspended`] if `uid` is suspended, according to the
+/// database. Also refreshes the cached copy used by [`check_cached`].
+pub async fn check(
+    db: &DatabaseConnection,
+    store: &dyn EphemeralStore,
+    uid: i32,
+) -> Result<(), Error> {
+    match active_suspension(db, uid).await? {
+        Some(suspension) => {
+            cache(store, &suspension).await?;
+            Err((&suspension).into())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Fails with [`Error::AccountSuspended`] if `uid` is suspended, according to the cache.
+pub async fn check_cached(store: &dyn EphemeralStore, uid: i32) -> Result<(), Error> {
+    let cached = store.get(&format!("suspended:{}", uid)).await?;
+
+    match cached.and_then(|cached| serde_json::from_str::<CachedSuspension>(&cached).ok()) {
+        Some(CachedSuspension { reason, until }) => Err(Error::AccountSuspended { reason, until }),
+        None => Ok(()),
+    }
+}
+
+/// Caches `suspension` until it ends, so that it lifts itself there.
+pub async fn cache(
+    store: &dyn EphemeralStore,
+    suspension: &suspension::Model,
+) -> Result<(), Error> {
+    let key = format!("suspended:{}", suspension.user_id);
+    let value = json!(CachedSuspension {
+        reason: suspension.reason.clone(),
+        until: suspension.ends_at,
+    })
+    .to_string();
+
+    let ttl = match suspension.ends_at {
+        Some(ends_at) => match (ends_at - Utc::now().naive_utc()).to_std() {
+            Ok(ttl) if !ttl.is_zero() => Some(ttl),
+            _ => return Ok(()),
+        },
+        None => None,
+    };
+
+    store.set(&key, &value, ttl).await
+}
+
+pub async fn clear_cache(store: &dyn EphemeralStore, uid: i32) -> Result<(), Error> {
+    store.delete(&format!("suspended:{}", uid)).await
+}
+
+/// Periodically marks suspensions whose end has passed as lifted and audits it.
+///
+/// Suspensions stop applying at their end regardless; this only keeps the records and
+/// the audit log in step.
+pub async fn lift_expired_suspensions(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(
+        state.config.accounts.suspension_lift_interval_seconds,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        let now = Utc::now().naive_utc();
+        let expired = match suspension::Entity::find()
+            .filter(suspension::Column::LiftedAt.is_null())
+            .filter(suspension::Column::EndsAt.lte(now))
+            .all(&state.db)
+            .await
+            .warn_err()
+        {
+            Ok(expired) => expired,
+            Err(_) => continue,
+        };
+
+        for suspension in expired {
+            let lifted = suspension::Entity::update_many()
+                .col_expr(suspension::Column::LiftedAt, Expr::col(suspension::Column::EndsAt).into())
+                .filter(suspension::Column::Id.eq(suspension.id))
+                .filter(suspension::Column::LiftedAt.is_null())
+                .exec(&state.db)
+                .await
+                .warn_err();
+            if !lifted.is_ok_and(|lifted| lifted.rows_affected == 1) {
+                continue;
+            }
+
+            AuditEvent::new(EventType::AccountUnsuspended)
+                .subject(suspension.user_id)
+                .details(json!({ "suspension_id": suspension.id, "expired": true }))
+                .record(&state.db)
+                .await;
+        }
+    }
+}
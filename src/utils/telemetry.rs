@@ -0,0 +1,77 @@
+use std::time::SystemTime;
+
+use opentelemetry::trace::{Span as _, SpanKind, Status, Tracer as _, TracerProvider as _};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use sea_orm::DatabaseConnection;
+use tracing::Level;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, Layer};
+
+use crate::config::{LogFormat, TelemetryConfig};
+
+const TRACER: &str = env!("CARGO_PKG_NAME");
+
+/// Installs the global subscriber, logging in the configured format.
+///
+/// Spans of this crate always get OpenTelemetry trace ids, so that they can be propagated
+/// to clients, but are only exported when an OTLP endpoint is set. The returned provider
+/// has to be shut down on exit to flush them.
+pub fn init(config: &TelemetryConfig) -> TracerProvider {
+    let mut builder = TracerProvider::builder().with_resource(Resource::new([KeyValue::new(
+        "service.name",
+        config.service_name.clone(),
+    )]));
+    let mut failure = None;
+    if let Some(endpoint) = &config.otlp_endpoint {
+        match SpanExporter::builder().with_http().with_endpoint(endpoint).build() {
+            Ok(exporter) => builder = builder.with_batch_exporter(exporter, runtime::Tokio),
+            Err(e) => failure = Some(e),
+        }
+    }
+    let provider = builder.build();
+    global::set_tracer_provider(provider.clone());
+
+    let logs = match config.log_format {
+        LogFormat::Text => fmt::layer().boxed(),
+        LogFormat::Json => fmt::layer().json().boxed(),
+    };
+    let spans = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(TRACER))
+        .with_filter(Targets::new().with_target(TRACER.replace('-', "_"), Level::TRACE));
+    tracing_subscriber::registry().with(logs).with(spans).init();
+
+    if let Some(e) = failure {
+        tracing::error!("failed to set up the OTLP exporter, spans will not be exported: {}", e);
+    }
+    provider
+}
+
+/// Records every query as a child span of the current one.
+///
+/// sea-orm only reports queries once they are done, so these spans are built from the
+/// reported duration rather than through `tracing`.
+pub fn trace_queries(db: &mut DatabaseConnection) {
+    db.set_metric_callback(|info| {
+        let end = SystemTime::now();
+        let tracer = global::tracer(TRACER);
+        let mut span = tracer
+            .span_builder("postgres query")
+            .with_kind(SpanKind::Client)
+            .with_start_time(end - info.elapsed)
+            .with_attributes([
+                KeyValue::new("db.system", "postgresql"),
+                KeyValue::new("db.statement", info.statement.sql.clone()),
+            ])
+            .start_with_context(&tracer, &tracing::Span::current().context());
+        if info.failed {
+            span.set_status(Status::error("query failed"));
+        }
+        span.end_with_timestamp(end);
+    });
+}
@@ -0,0 +1,225 @@
+use chrono::{Duration, Utc};
+use rand::Rng;
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{
+    ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter, SqlErr,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use validator::ValidationError;
+
+use crate::data::error::Error;
+use crate::entity::{user, username_redirect};
+use crate::store::EphemeralStore;
+use crate::utils::db::StanderizeError;
+
+const MIN_LENGTH: usize = 3;
+const MAX_LENGTH: usize = 20;
+/// Handles tried by [`derive_username`] before giving up.
+const DERIVE_ATTEMPTS: usize = 5;
+
+/// Handles that could be mistaken for the service itself or collide with routes.
+const RESERVED: &[&str] = &[
+    "about", "account", "admin", "administrator", "api", "auth", "help", "login", "logout",
+    "me", "moderator", "nuke", "null", "oauth", "official", "register", "root", "security",
+    "settings", "staff", "support", "system", "undefined", "users", "verify", "websxz",
+];
+
+#[derive(Serialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Availability {
+    #[serde(rename = "available")]
+    Available,
+    #[serde(rename = "invalid")]
+    Invalid,
+    #[serde(rename = "reserved")]
+    Reserved,
+    #[serde(rename = "taken")]
+    Taken,
+}
+
+/// Handles are compared case-insensitively, so they are stored in lowercase.
+pub fn normalize(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+/// Handles are 3 to 20 ASCII letters, digits or underscores, starting with a letter.
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    let username = normalize(username);
+
+    if !(MIN_LENGTH..=MAX_LENGTH).contains(&username.len()) {
+        let mut error = ValidationError::new("length");
+        error.add_param("min".into(), &MIN_LENGTH);
+        error.add_param("max".into(), &MAX_LENGTH);
+        return Err(error);
+    }
+    if !username.starts_with(|c: char| c.is_ascii_lowercase())
+        || !username
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(ValidationError::new("charset"));
+    }
+    if RESERVED.contains(&username.as_str()) {
+        return Err(ValidationError::new("reserved"));
+    }
+
+    Ok(())
+}
+
+/// Whether `username` could be taken by `uid`, or by a new account if `uid` is `None`.
+///
+/// Handles held by another user or by a live redirect of another user are taken, and so
+/// are handles claimed by a pending registration unless `email` is the one claiming it.
+pub async fn availability(
+    db: &DatabaseConnection,
+    store: &dyn EphemeralStore,
+    username: &str,
+    uid: Option<i32>,
+    email: Option<&str>,
+) -> Result<Availability, Error> {
+    if let Err(e) = validate_username(username) {
+        return Ok(if e.code == "reserved" {
+            Availability::Reserved
+        } else {
+            Availability::Invalid
+        });
+    }
+    let username = normalize(username);
+
+    let owner = user::Entity::find()
+        .filter(user::Column::Username.eq(&username))
+        .one(db)
+        .await
+        .warn_err()?;
+    if owner.is_some_and(|owner| Some(owner.id) != uid) {
+        return Ok(Availability::Taken);
+    }
+
+    let redirect = username_redirect::Entity::find_by_id(&username)
+        .filter(username_redirect::Column::ExpiresAt.gt(Utc::now().naive_utc()))
+        .one(db)
+        .await
+        .warn_err()?;
+    if redirect.is_some_and(|redirect| Some(redirect.user_id) != uid) {
+        return Ok(Availability::Taken);
+    }
+
+    let pending = store.username_claim(&username).await?;
+    if pending.is_some_and(|pending| Some(pending.as_str()) != email) {
+        return Ok(Availability::Taken);
+    }
+
+    Ok(Availability::Available)
+}
+
+/// A handle for a registration that did not pick one: the local part of `email`, with
+/// random digits appended if that is not available.
+pub async fn derive_username(
+    db: &DatabaseConnection,
+    store: &dyn EphemeralStore,
+    email: &str,
+) -> Result<String, Error> {
+    let local = email.split('@').next().unwrap_or_default();
+    let mut base: String = normalize(local)
+        .chars()
+        .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '_')
+        .collect();
+    if !base.starts_with(|c: char| c.is_ascii_lowercase()) {
+        base.insert(0, 'u');
+    }
+    base.truncate(MAX_LENGTH - 4);
+
+    for attempt in 0..DERIVE_ATTEMPTS {
+        let candidate = if attempt == 0 {
+            base.clone()
+        } else {
+            format!("{}{:04}", base, rand::thread_rng().gen_range(0..10_000))
+        };
+        if availability(db, store, &candidate, None, Some(email)).await? == Availability::Available
+        {
+            return Ok(candidate);
+        }
+    }
+
+    Err(Error::UsernameTaken)
+}
+
+/// Finds the user a handle belongs to, following unexpired redirects of old handles.
+pub async fn resolve(db: &DatabaseConnection, username: &str) -> Result<Option<user::Model>, Error> {
+    let username = normalize(username);
+
+    if let Some(user) = user::Entity::find()
+        .filter(user::Column::Username.eq(&username))
+        .one(db)
+        .await
+        .warn_err()?
+    {
+        return Ok(Some(user));
+    }
+
+    let Some(redirect) = username_redirect::Entity::find_by_id(&username)
+        .filter(username_redirect::Column::ExpiresAt.gt(Utc::now().naive_utc()))
+        .one(db)
+        .await
+        .warn_err()?
+    else {
+        return Ok(None);
+    };
+
+    user::Entity::find_by_id(redirect.user_id)
+        .one(db)
+        .await
+        .warn_err()
+}
+
+/// Renames `user` to `username`, which should have been found available, and keeps the
+/// old handle pointing at them for `redirect_days`.
+///
+/// Meant to run in a transaction, so that the redirect and the rename happen together.
+pub async fn rename<C: ConnectionTrait>(
+    db: &C,
+    user: &user::Model,
+    username: &str,
+    redirect_days: i64,
+) -> Result<(), Error> {
+    let now = Utc::now().naive_utc();
+
+    username_redirect::Entity::insert(username_redirect::ActiveModel {
+        username: ActiveValue::Set(user.username.clone()),
+        user_id: ActiveValue::Set(user.id),
+        created_at: ActiveValue::Set(now),
+        expires_at: ActiveValue::Set(now + Duration::days(redirect_days)),
+    })
+    .on_conflict(
+        OnConflict::column(username_redirect::Column::Username)
+            .update_columns([
+                username_redirect::Column::UserId,
+                username_redirect::Column::CreatedAt,
+                username_redirect::Column::ExpiresAt,
+            ])
+            .to_owned(),
+    )
+    .exec(db)
+    .await
+    .warn_err()?;
+
+    // Going back to an old handle turns its redirect into the real thing again.
+    username_redirect::Entity::delete_many()
+        .filter(username_redirect::Column::Username.eq(username))
+        .filter(username_redirect::Column::UserId.eq(user.id))
+        .exec(db)
+        .await
+        .warn_err()?;
+
+    let renamed = user::Entity::update_many()
+        .col_expr(user::Column::Username, Expr::value(username))
+        .filter(user::Column::Id.eq(user.id))
+        .exec(db)
+        .await;
+    match renamed {
+        Err(e) if matches!(e.sql_err(), Some(SqlErr::UniqueConstraintViolation(_))) => {
+            Err(Error::UsernameTaken)
+        }
+        renamed => renamed.map(|_| ()).warn_err(),
+    }
+}